@@ -0,0 +1,11 @@
+use opentelemetry_tonic::build::TracedGenerator;
+
+fn main() -> std::io::Result<()> {
+    // no protoc needed on the machine running the tests
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path().unwrap());
+    println!("cargo:rerun-if-changed=proto");
+
+    prost_build::Config::new()
+        .service_generator(Box::new(TracedGenerator::new(tonic_build::configure().service_generator())))
+        .compile_protos(&["proto/helloworld.proto"], &["proto"])
+}
@@ -0,0 +1,98 @@
+//! tonic-build output of proto/helloworld.proto, with the traced wrappers written by `TracedGenerator`.
+
+pub mod helloworld {
+    tonic::include_proto!("helloworld");
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::pin::Pin;
+
+    use opentelemetry::global;
+    use opentelemetry::sdk::propagation::TraceContextPropagator;
+    use opentelemetry::trace::SpanKind;
+    use opentelemetry_tonic::testing::SpanRecorder;
+    use tokio_stream::{Stream, StreamExt};
+    use tonic::{Request, Response, Status, Streaming};
+
+    use crate::helloworld::greeter_client::GreeterClient;
+    use crate::helloworld::greeter_server::Greeter;
+    use crate::helloworld::greeter_traced::{traced_greeter_server, TracedGreeterClient, SERVICE};
+    use crate::helloworld::{HelloReply, HelloRequest};
+
+    struct MyGreeter;
+
+    #[tonic::async_trait]
+    impl Greeter for MyGreeter {
+        async fn say_hello(&self, request: Request<HelloRequest>) -> Result<Response<HelloReply>, Status> {
+            let message = format!("hello {}", request.into_inner().name);
+            Ok(Response::new(HelloReply { message }))
+        }
+
+        type ChatStream = Pin<Box<dyn Stream<Item = Result<HelloReply, Status>> + Send>>;
+
+        async fn chat(&self, request: Request<Streaming<HelloRequest>>) -> Result<Response<Self::ChatStream>, Status> {
+            let mut requests = request.into_inner();
+            let mut replies = Vec::new();
+            while let Some(request) = requests.message().await? {
+                replies.push(Ok(HelloReply { message: request.name }));
+            }
+            Ok(Response::new(Box::pin(tokio_stream::iter(replies))))
+        }
+    }
+
+    #[tokio::test]
+    async fn traced_round_trip() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let recorder = SpanRecorder::default();
+        let (_provider, subscriber) = recorder.subscriber();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        // the traced server is the transport of the traced client
+        let mut client = TracedGreeterClient::new(GreeterClient::new(traced_greeter_server(MyGreeter)));
+        let reply = client.say_hello(HelloRequest { name: "world".to_string() }).await.unwrap();
+        assert_eq!(reply.into_inner().message, "hello world");
+
+        let requests = ["a", "b"].map(|name| HelloRequest { name: name.to_string() });
+        let replies: Vec<_> = client
+            .chat(tokio_stream::iter(requests))
+            .await
+            .unwrap()
+            .into_inner()
+            .map(|reply| reply.unwrap().message)
+            .collect()
+            .await;
+        assert_eq!(replies, ["a", "b"]);
+
+        assert_eq!(SERVICE, "helloworld.Greeter");
+        let spans = recorder.spans();
+        for method in ["SayHello", "Chat"] {
+            let name = format!("helloworld.Greeter/{}", method);
+            let span = |kind: SpanKind| {
+                spans
+                    .iter()
+                    .find(|span| span.name == name && span.span_kind == kind)
+                    .unwrap_or_else(|| panic!("no {:?} span {}", kind, name))
+            };
+            let (client_span, server_span) = (span(SpanKind::Client), span(SpanKind::Server));
+            assert_eq!(server_span.span_context.trace_id(), client_span.span_context.trace_id());
+            assert_eq!(server_span.parent_span_id, client_span.span_context.span_id());
+        }
+
+        // the Chat server span lasts until the response stream is done, with its messages
+        let chat = spans
+            .iter()
+            .find(|span| span.name == "helloworld.Greeter/Chat" && span.span_kind == SpanKind::Server)
+            .unwrap();
+        let sent = chat
+            .events
+            .iter()
+            .filter(|event| {
+                event.name == "message"
+                    && event.attributes.iter().any(|kv| kv.key.as_str() == "message.type" && kv.value.as_str() == "SENT")
+            })
+            .count();
+        assert_eq!(sent, 2);
+    }
+}
@@ -0,0 +1,193 @@
+//! `#[traced_rpc]`, re-exported by opentelemetry-tonic with its `macros` feature.
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{parse_macro_input, FnArg, Ident, ImplItemFn, LitStr, Pat, ReturnType, Type};
+
+
+/// Trace a tonic service method: parent a server span on the context sent by the client
+/// (like `tracing_parent_span_from_req`), name it "package.Service/Method" with the RPC attributes,
+/// and record the returned `Result<Response<_>, Status>`.
+///
+/// `method` defaults to the method name in UpperCamelCase, e.g. `say_hello` -> "SayHello".
+/// That doesn't give back proto names with acronyms or some digits, which tonic-build snake-cases
+/// one way: `GetHTTPURL` becomes `get_http_url`, traced as "GetHttpUrl". Set `method = "..."`
+/// whenever the proto name isn't plain UpperCamelCase, nothing checks it against the proto.
+/// Works on `async fn` and on methods expanded by `#[tonic::async_trait]`.
+/// Don't combine with `TraceExtractLayer`, which already opens a server span.
+///
+/// ```ignore
+/// #[tonic::async_trait]
+/// impl Greeter for MyGreeter {
+///     #[traced_rpc(service = "helloworld.Greeter")]
+///     async fn say_hello(&self, request: Request<HelloRequest>) -> Result<Response<HelloReply>, Status> {
+///         ...
+///     }
+///
+///     #[traced_rpc(service = "helloworld.Greeter", method = "GetHTTPURL")]
+///     async fn get_http_url(&self, request: Request<UrlRequest>) -> Result<Response<UrlReply>, Status> {
+///         ...
+///     }
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn traced_rpc(args: TokenStream, item: TokenStream) -> TokenStream {
+    let mut service: Option<LitStr> = None;
+    let mut method: Option<LitStr> = None;
+    let parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("service") {
+            service = Some(meta.value()?.parse()?);
+            Ok(())
+        } else if meta.path.is_ident("method") {
+            method = Some(meta.value()?.parse()?);
+            Ok(())
+        } else {
+            Err(meta.error("expected `service` or `method`"))
+        }
+    });
+    parse_macro_input!(args with parser);
+    let item = parse_macro_input!(item as ImplItemFn);
+
+    match expand(service, method, item) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand(service: Option<LitStr>, method: Option<LitStr>, item: ImplItemFn) -> syn::Result<proc_macro2::TokenStream> {
+    let service = service.ok_or_else(|| {
+        syn::Error::new(Span::call_site(), "missing `service`, e.g. #[traced_rpc(service = \"helloworld.Greeter\")]")
+    })?;
+    let method = method.unwrap_or_else(|| LitStr::new(&upper_camel_case(&item.sig.ident), item.sig.ident.span()));
+    let request = request_arg(&item)?.clone();
+
+    let ImplItemFn { attrs, vis, defaultness, sig, block } = item;
+    let span = quote! {
+        let __traced_rpc_span = ::opentelemetry_tonic::__private::server_span(&#request, #service, #method);
+    };
+    let body = match (&sig.asyncness, &sig.output) {
+        (Some(_), ReturnType::Type(_, output)) => quote! {
+            #span
+            ::opentelemetry_tonic::__private::traced(__traced_rpc_span, async move {
+                let __traced_rpc_ret: #output = #block;
+                __traced_rpc_ret
+            })
+            .await
+        },
+        // expanded by async_trait, the body returns a boxed future
+        (None, ReturnType::Type(..)) => quote! {
+            #span
+            ::std::boxed::Box::pin(::opentelemetry_tonic::__private::traced(__traced_rpc_span, #block))
+        },
+        (_, ReturnType::Default) => {
+            return Err(syn::Error::new_spanned(&sig, "expected a method returning `Result<Response<_>, Status>`"))
+        }
+    };
+
+    Ok(quote! {
+        #(#attrs)*
+        #vis #defaultness #sig {
+            #body
+        }
+    })
+}
+
+// the `request: Request<T>` argument
+fn request_arg(item: &ImplItemFn) -> syn::Result<&Ident> {
+    for input in &item.sig.inputs {
+        let FnArg::Typed(arg) = input else { continue };
+        let Type::Path(ty) = arg.ty.as_ref() else { continue };
+        if ty.path.segments.last().is_none_or(|segment| segment.ident != "Request") {
+            continue;
+        }
+        return match arg.pat.as_ref() {
+            Pat::Ident(pat) => Ok(&pat.ident),
+            pat => Err(syn::Error::new_spanned(pat, "bind the request to a name, e.g. `request: Request<T>`")),
+        };
+    }
+    Err(syn::Error::new_spanned(&item.sig, "expected a `Request<T>` argument"))
+}
+
+// say_hello -> SayHello
+fn upper_camel_case(ident: &Ident) -> String {
+    let ident = ident.to_string();
+    ident
+        .trim_start_matches("r#")
+        .split('_')
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            chars.next().map(|first| first.to_ascii_uppercase()).into_iter().chain(chars).collect::<String>()
+        })
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use quote::format_ident;
+    use syn::{parse_quote, ImplItemFn, LitStr};
+
+    use super::{expand, request_arg, upper_camel_case};
+
+    fn error(item: ImplItemFn) -> String {
+        let service = LitStr::new("helloworld.Greeter", proc_macro2::Span::call_site());
+        expand(Some(service), None, item).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn request_argument() {
+        let item: ImplItemFn = parse_quote! {
+            async fn say_hello(&self, request: tonic::Request<HelloRequest>) -> Result<Response<HelloReply>, Status> {}
+        };
+        assert_eq!(request_arg(&item).unwrap(), "request");
+
+        let missing = parse_quote! {
+            async fn say_hello(&self, name: String) -> Result<Response<HelloReply>, Status> {}
+        };
+        assert_eq!(error(missing), "expected a `Request<T>` argument");
+        let unnamed = parse_quote! {
+            async fn say_hello(&self, _: Request<HelloRequest>) -> Result<Response<HelloReply>, Status> {}
+        };
+        assert_eq!(error(unnamed), "bind the request to a name, e.g. `request: Request<T>`");
+        let no_return = parse_quote! {
+            async fn say_hello(&self, request: Request<HelloRequest>) {}
+        };
+        assert_eq!(error(no_return), "expected a method returning `Result<Response<_>, Status>`");
+
+        let item: ImplItemFn = parse_quote! {
+            async fn say_hello(&self, request: Request<HelloRequest>) -> Result<Response<HelloReply>, Status> {}
+        };
+        let missing_service = expand(None, None, item).unwrap_err().to_string();
+        assert!(missing_service.starts_with("missing `service`"), "{}", missing_service);
+    }
+
+    #[test]
+    fn method_names() {
+        assert_eq!(upper_camel_case(&format_ident!("say_hello")), "SayHello");
+        assert_eq!(upper_camel_case(&format_ident!("r#type")), "Type");
+        assert_eq!(upper_camel_case(&format_ident!("_private__call_")), "PrivateCall");
+        assert_eq!(upper_camel_case(&format_ident!("get2_items")), "Get2Items");
+        assert_eq!(upper_camel_case(&format_ident!("Check")), "Check");
+        // acronyms are lost, see `method`
+        assert_eq!(upper_camel_case(&format_ident!("get_http_url")), "GetHttpUrl");
+    }
+
+    #[test]
+    fn method_override() {
+        let item = || -> ImplItemFn {
+            parse_quote! {
+                async fn get_http_url(&self, request: Request<UrlRequest>) -> Result<Response<UrlReply>, Status> {}
+            }
+        };
+        let service = || Some(LitStr::new("helloworld.Greeter", proc_macro2::Span::call_site()));
+
+        let derived = expand(service(), None, item()).unwrap().to_string();
+        assert!(derived.contains("\"GetHttpUrl\""), "{}", derived);
+        let method = LitStr::new("GetHTTPURL", proc_macro2::Span::call_site());
+        let overridden = expand(service(), Some(method), item()).unwrap().to_string();
+        assert!(overridden.contains("\"GetHTTPURL\""), "{}", overridden);
+        assert!(!overridden.contains("GetHttpUrl"), "{}", overridden);
+    }
+}
@@ -0,0 +1,148 @@
+//! W3C Baggage, see <https://www.w3.org/TR/baggage/>
+//!
+//! These helpers use `BaggagePropagator` directly, so they work whatever the global propagator is,
+//! the `_with` variants take the propagator to use instead.
+
+use opentelemetry::baggage::BaggageExt;
+use opentelemetry::propagation::{Extractor, TextMapPropagator};
+use opentelemetry::sdk::propagation::BaggagePropagator;
+use opentelemetry::{Context, KeyValue};
+
+use tonic::Request;
+
+use crate::{CheckedMetadataInjector, EncodingExtractor, EncodingInjector, Encodings, InjectionReport, MetadataExtractor};
+
+
+/// Context holding the baggage sent by the client, read it with `cx.baggage()`.
+pub fn baggage_cx_from_req<T>(request: &Request<T>) -> Context {
+    baggage_cx_from_req_with(request, &BaggagePropagator::new())
+}
+
+/// Same as [`baggage_cx_from_req`], with an explicit propagator, e.g. a composite one.
+pub fn baggage_cx_from_req_with<T>(request: &Request<T>, propagator: &dyn TextMapPropagator) -> Context {
+    propagator.extract(&MetadataExtractor(request.metadata()))
+}
+
+/// Same as [`baggage_cx_from_req_with`], decoding the values encoded per `encodings`.
+pub fn baggage_cx_from_req_with_encodings<T>(
+    request: &Request<T>,
+    propagator: &dyn TextMapPropagator,
+    encodings: &Encodings,
+) -> Context {
+    propagator.extract(&EncodingExtractor::new(request.metadata(), encodings))
+}
+
+/// Value of a single baggage entry sent by the client.
+pub fn baggage_value_from_req<T>(request: &Request<T>, key: &str) -> Option<String> {
+    baggage_value_from_req_with(request, &BaggagePropagator::new(), key)
+}
+
+/// Same as [`baggage_value_from_req`], with an explicit propagator.
+pub fn baggage_value_from_req_with<T>(request: &Request<T>, propagator: &dyn TextMapPropagator, key: &str) -> Option<String> {
+    baggage_cx_from_req_with(request, propagator)
+        .baggage()
+        .get(key.to_string())
+        .map(|value| value.to_string())
+}
+
+/// Send the baggage of the current thread-bound `Context` plus `entries` with the request.
+/// `entries` override current entries with the same key.
+/// Returns the entries which could not be sent, each also logged with `tracing::warn!`.
+pub fn baggage_to_req<T, I>(request: &mut Request<T>, entries: I) -> InjectionReport
+where
+    I: IntoIterator<Item = KeyValue>,
+{
+    baggage_to_req_with(request, &BaggagePropagator::new(), entries)
+}
+
+/// Same as [`baggage_to_req`], with an explicit propagator.
+pub fn baggage_to_req_with<T, I>(request: &mut Request<T>, propagator: &dyn TextMapPropagator, entries: I) -> InjectionReport
+where
+    I: IntoIterator<Item = KeyValue>,
+{
+    let cx = Context::current_with_baggage(entries);
+    let mut injector = CheckedMetadataInjector::new(request.metadata_mut());
+    propagator.inject_context(&cx, &mut injector);
+    injector.into_report()
+}
+
+/// Same as [`baggage_to_req_with`], encoding the values per `encodings`,
+/// read them back with [`baggage_cx_from_req_with_encodings`].
+pub fn baggage_to_req_with_encodings<T, I>(
+    request: &mut Request<T>,
+    propagator: &dyn TextMapPropagator,
+    entries: I,
+    encodings: &Encodings,
+) -> InjectionReport
+where
+    I: IntoIterator<Item = KeyValue>,
+{
+    let cx = Context::current_with_baggage(entries);
+    let mut injector = EncodingInjector::new(request.metadata_mut(), encodings);
+    propagator.inject_context(&cx, &mut injector);
+    injector.into_report()
+}
+
+pub(crate) fn baggage_cx(extractor: &dyn Extractor) -> Context {
+    BaggagePropagator::new().extract(extractor)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::baggage::BaggageExt;
+    use opentelemetry::{Context, KeyValue};
+    use tonic::Request;
+
+    use opentelemetry::sdk::propagation::{BaggagePropagator, TextMapCompositePropagator, TraceContextPropagator};
+
+    use super::{
+        baggage_cx_from_req, baggage_cx_from_req_with, baggage_cx_from_req_with_encodings, baggage_to_req,
+        baggage_to_req_with, baggage_to_req_with_encodings, baggage_value_from_req, baggage_value_from_req_with,
+    };
+    use crate::testing::TenantPropagator;
+    use crate::{Encoding, Encodings};
+
+    #[test]
+    fn round_trip() {
+        let _cx = Context::current_with_baggage(vec![KeyValue::new("user.id", "42")]).attach();
+
+        let mut request = Request::new(());
+        assert!(baggage_to_req(&mut request, vec![KeyValue::new("tenant.id", "acme")]).is_ok());
+
+        assert_eq!(baggage_value_from_req(&request, "tenant.id").as_deref(), Some("acme"));
+        assert_eq!(baggage_value_from_req(&request, "user.id").as_deref(), Some("42"));
+        assert_eq!(baggage_value_from_req(&request, "missing"), None);
+        assert_eq!(baggage_cx_from_req(&request).baggage().len(), 2);
+    }
+
+    #[test]
+    fn explicit_propagator() {
+        let composite = TextMapCompositePropagator::new(vec![
+            Box::new(TraceContextPropagator::new()),
+            Box::new(BaggagePropagator::new()),
+        ]);
+        let mut request = Request::new(());
+        assert!(baggage_to_req_with(&mut request, &composite, vec![KeyValue::new("tenant.id", "acme")]).is_ok());
+
+        assert_eq!(baggage_value_from_req_with(&request, &composite, "tenant.id").as_deref(), Some("acme"));
+        assert_eq!(baggage_value_from_req_with(&request, &TraceContextPropagator::new(), "tenant.id"), None);
+    }
+
+    #[test]
+    fn encodings() {
+        let encodings = Encodings::new().with_key("x-tenant", Encoding::Percent);
+        let mut request = Request::new(());
+        let propagator = TenantPropagator::new();
+        let entries = vec![KeyValue::new("tenant", "Zürich NY")];
+        let report = baggage_to_req_with_encodings(&mut request, &propagator, entries, &encodings);
+        assert!(report.is_ok());
+        assert_eq!(request.metadata().get("x-tenant").unwrap(), "Z%C3%BCrich%20NY");
+
+        let value = |cx: Context| cx.baggage().get("tenant").map(|value| value.to_string());
+        let cx = baggage_cx_from_req_with_encodings(&request, &propagator, &encodings);
+        assert_eq!(value(cx).as_deref(), Some("Zürich NY"));
+        let cx = baggage_cx_from_req_with(&request, &propagator);
+        assert_eq!(value(cx).as_deref(), Some("Z%C3%BCrich%20NY"));
+    }
+}
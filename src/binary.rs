@@ -0,0 +1,171 @@
+use std::str::FromStr;
+
+use opentelemetry::propagation::{Extractor, Injector};
+
+use tonic::metadata::{Ascii, Binary, KeyAndValueRef, MetadataKey, MetadataMap, MetadataValue, ValueRef};
+
+use crate::headers::is_binary;
+
+
+/// `Injector` which also writes `-bin` keys: their value is sent as bytes, base64-encoded on the wire,
+/// where `MetadataInjector` drops them.
+pub struct BinaryMetadataInjector<'a>(pub &'a mut MetadataMap);
+
+impl<'a> BinaryMetadataInjector<'a> {
+    /// Set a `-bin` key to `value`.  Does nothing if the key is not a valid binary key
+    pub fn set_bytes(&mut self, key: &str, value: &[u8]) {
+        if let Ok(key) = MetadataKey::<Binary>::from_str(key) {
+            self.0.insert_bin(key, MetadataValue::from_bytes(value));
+        }
+    }
+}
+
+impl<'a> Injector for BinaryMetadataInjector<'a> {
+    /// Set a key and value in the MetadataMap, the UTF-8 bytes of the value for a `-bin` key.
+    /// Does nothing if the key or value are not valid inputs
+    fn set(&mut self, key: &str, value: String) {
+        if is_binary(key) {
+            return self.set_bytes(key, value.as_bytes());
+        }
+        if let Ok(key) = MetadataKey::<Ascii>::from_str(key) {
+            if let Ok(val) = value.parse() {
+                self.0.insert(key, val);
+            }
+        }
+    }
+}
+
+
+/// `Extractor` which also reads `-bin` keys, base64-decoded, where `MetadataExtractor` returns `None`.
+///
+/// `get` returns binary values which are valid UTF-8, `get_bytes` any of them,
+/// and `entries` every `(key, value)` pair for propagators working on the metadata itself.
+pub struct BinaryMetadataExtractor<'a> {
+    metadata: &'a MetadataMap,
+    // decoded binary values, first one per key, `get` borrows from here
+    decoded: Vec<(&'a str, String)>,
+}
+
+impl<'a> BinaryMetadataExtractor<'a> {
+    pub fn new(metadata: &'a MetadataMap) -> Self {
+        let mut decoded: Vec<(&'a str, String)> = Vec::new();
+        for (key, value) in entries(metadata) {
+            let ValueRef::Binary(value) = value else { continue };
+            if decoded.iter().any(|(decoded_key, _)| *decoded_key == key) {
+                continue;
+            }
+            let text = value.to_bytes().ok().and_then(|bytes| String::from_utf8(bytes.to_vec()).ok());
+            if let Some(text) = text {
+                decoded.push((key, text));
+            }
+        }
+        BinaryMetadataExtractor { metadata, decoded }
+    }
+
+    /// Decoded value of a `-bin` key, `None` for other keys or invalid base64.
+    pub fn get_bytes(&self, key: &str) -> Option<Vec<u8>> {
+        if !is_binary(key) {
+            return None;
+        }
+        self.metadata.get_bin(key).and_then(|value| value.to_bytes().ok()).map(|bytes| bytes.to_vec())
+    }
+
+    /// Every `(key, value)` pair of the metadata, in order, several for a key with several values.
+    pub fn entries(&self) -> impl Iterator<Item = (&'a str, ValueRef<'a>)> {
+        entries(self.metadata)
+    }
+}
+
+impl<'a> Extractor for BinaryMetadataExtractor<'a> {
+    /// Get a value for a key from the MetadataMap, decoded for a `-bin` key.
+    /// If the value can't be converted to &str, returns None
+    fn get(&self, key: &str) -> Option<&str> {
+        if is_binary(key) {
+            let key = key.to_ascii_lowercase();
+            return self.decoded.iter().find(|(decoded_key, _)| *decoded_key == key).map(|(_, value)| value.as_str());
+        }
+        self.metadata.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    /// Collect all the keys from the MetadataMap.
+    fn keys(&self) -> Vec<&str> {
+        let mut keys: Vec<&str> = Vec::new();
+        for (key, _) in self.entries() {
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+        keys
+    }
+}
+
+fn entries(metadata: &MetadataMap) -> impl Iterator<Item = (&str, ValueRef<'_>)> {
+    metadata.iter().map(|entry| match entry {
+        KeyAndValueRef::Ascii(key, value) => (key.as_str(), ValueRef::Ascii(value)),
+        KeyAndValueRef::Binary(key, value) => (key.as_str(), ValueRef::Binary(value)),
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::propagation::{Extractor, Injector};
+    use tonic::metadata::{MetadataMap, MetadataValue, ValueRef};
+
+    use super::{BinaryMetadataExtractor, BinaryMetadataInjector};
+    use crate::MetadataExtractor;
+
+    #[test]
+    fn binary_round_trip() {
+        let mut metadata = MetadataMap::new();
+        let mut injector = BinaryMetadataInjector(&mut metadata);
+        injector.set("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".to_string());
+        injector.set("tenant-bin", "acmé".to_string());
+        injector.set_bytes("grpc-trace-bin", &[0, 0, 0xff]);
+        injector.set_bytes("not-binary", &[0]);
+        injector.set("bad key-bin", "value".to_string());
+        injector.set("a€bc", "value".to_string());
+
+        // base64 on the wire
+        let headers = metadata.clone().into_headers();
+        assert_eq!(headers.get("tenant-bin").unwrap(), "YWNtw6k");
+        assert_eq!(headers.len(), 3);
+
+        let extractor = BinaryMetadataExtractor::new(&metadata);
+        assert_eq!(extractor.get("tenant-bin"), Some("acmé"));
+        assert_eq!(extractor.get("Tenant-Bin"), Some("acmé"));
+        assert_eq!(extractor.get("grpc-trace-bin"), None);
+        assert_eq!(extractor.get_bytes("grpc-trace-bin"), Some(vec![0, 0, 0xff]));
+        assert_eq!(extractor.get_bytes("traceparent"), None);
+        assert_eq!(extractor.get("a€bc"), None);
+        assert_eq!(extractor.get_bytes("a€-bin"), None);
+        assert_eq!(extractor.get("traceparent"), MetadataExtractor(&metadata).get("traceparent"));
+        assert_eq!(MetadataExtractor(&metadata).get("tenant-bin"), None);
+
+        let mut keys = extractor.keys();
+        keys.sort_unstable();
+        assert_eq!(keys, ["grpc-trace-bin", "tenant-bin", "traceparent"]);
+    }
+
+    #[test]
+    fn entries() {
+        let mut metadata = MetadataMap::new();
+        metadata.append("baggage", MetadataValue::from_static("a=1"));
+        metadata.append("baggage", MetadataValue::from_static("b=2"));
+        metadata.insert_bin("grpc-trace-bin", MetadataValue::from_bytes(&[1, 2]));
+
+        let extractor = BinaryMetadataExtractor::new(&metadata);
+        let entries: Vec<_> = extractor
+            .entries()
+            .map(|(key, value)| match value {
+                ValueRef::Ascii(value) => (key, value.as_bytes().to_vec()),
+                ValueRef::Binary(value) => (key, value.to_bytes().unwrap().to_vec()),
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            [("baggage", b"a=1".to_vec()), ("baggage", b"b=2".to_vec()), ("grpc-trace-bin", vec![1, 2])]
+        );
+        assert_eq!(extractor.keys(), ["baggage", "grpc-trace-bin"]);
+    }
+}
@@ -0,0 +1,329 @@
+//! build.rs helper writing traced wrappers next to the client & server generated by tonic-build,
+//! with the injection/extraction and the span name of every method written out at compile time.
+//! Needs the `build` feature, e.g. in `[build-dependencies]`.
+//!
+//! For a service `helloworld.Greeter` it writes a module `greeter_traced`, next to the tonic-build output:
+//! - `TracedGreeterClient<T>`, the methods of `GreeterClient<T>` with a client span each
+//! - `TracedGreeter<T>`, implementing `greeter_server::Greeter` over a `T: Greeter` with a server span per method,
+//!   and `traced_greeter_server(greeter)` wrapping it into `GreeterServer`. The response stream of a
+//!   server-streaming method is a [`TracedStream`](crate::TracedStream) holding the span, which ends
+//!   with the stream rather than when the handler returns it
+//!
+//! [`TracedGenerator`] adds them to the tonic-build output of every service:
+//!
+//! ```ignore
+//! // build.rs
+//! prost_build::Config::new()
+//!     .service_generator(Box::new(TracedGenerator::new(tonic_build::configure().service_generator())))
+//!     .compile_protos(&["proto/helloworld.proto"], &["proto"])?;
+//! ```
+//!
+//! [`generate`] & [`write_to`] write them from a [`Service`] filled by hand.
+
+use std::fmt::Write;
+
+
+/// A gRPC service, as declared in the proto.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Service {
+    /// Proto package, e.g. "helloworld", empty without package.
+    pub package: String,
+    /// Proto service name, e.g. "Greeter".
+    pub name: String,
+    /// Service name in the generated code, e.g. "Greeter", prefix of the client & server types.
+    pub rust_name: String,
+    pub methods: Vec<Method>,
+}
+
+/// A method of a [`Service`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Method {
+    /// Proto method name, e.g. "SayHello".
+    pub name: String,
+    /// Method name in the generated code, e.g. "say_hello".
+    pub rust_name: String,
+    /// Request message path as written by tonic-build, e.g. "super::HelloRequest".
+    pub input_type: String,
+    /// Response message path as written by tonic-build, e.g. "super::HelloReply".
+    pub output_type: String,
+    pub client_streaming: bool,
+    pub server_streaming: bool,
+}
+
+impl Service {
+    /// "package.Service", the prefix of the span names.
+    pub fn full_name(&self) -> String {
+        match self.package.is_empty() {
+            true => self.name.clone(),
+            false => format!("{}.{}", self.package, self.name),
+        }
+    }
+}
+
+impl From<&prost_build::Service> for Service {
+    fn from(service: &prost_build::Service) -> Self {
+        Service {
+            package: service.package.clone(),
+            name: service.proto_name.clone(),
+            rust_name: service.name.clone(),
+            methods: service
+                .methods
+                .iter()
+                .map(|method| Method {
+                    name: method.proto_name.clone(),
+                    rust_name: method.name.clone(),
+                    input_type: type_path(&method.input_type),
+                    output_type: type_path(&method.output_type),
+                    client_streaming: method.client_streaming,
+                    server_streaming: method.server_streaming,
+                })
+                .collect(),
+        }
+    }
+}
+
+// message path from the wrappers module, as tonic-build resolves it from its client & server modules
+fn type_path(rust_type: &str) -> String {
+    match rust_type == "()" || rust_type.starts_with("::") || rust_type.starts_with("crate::") {
+        true => rust_type.to_string(),
+        false => format!("super::{}", rust_type),
+    }
+}
+
+
+/// `prost_build::ServiceGenerator` adding the traced wrappers of every service
+/// to the output of `inner`, usually `tonic_build::configure().service_generator()`.
+pub struct TracedGenerator {
+    inner: Box<dyn prost_build::ServiceGenerator>,
+}
+
+impl TracedGenerator {
+    pub fn new(inner: Box<dyn prost_build::ServiceGenerator>) -> Self {
+        TracedGenerator { inner }
+    }
+}
+
+impl prost_build::ServiceGenerator for TracedGenerator {
+    fn generate(&mut self, service: prost_build::Service, buf: &mut String) {
+        buf.push_str(&generate(&Service::from(&service)));
+        self.inner.generate(service, buf);
+    }
+
+    // tonic-build writes its clients & servers here
+    fn finalize(&mut self, buf: &mut String) {
+        self.inner.finalize(buf);
+    }
+
+    fn finalize_package(&mut self, package: &str, buf: &mut String) {
+        self.inner.finalize_package(package, buf);
+    }
+}
+
+
+/// Source of the traced wrappers of `service`.
+pub fn generate(service: &Service) -> String {
+    let snake = snake_case(&service.rust_name);
+    let mut out = String::new();
+
+    writeln!(out, "/// Traced wrappers of the `{}` client and server.", service.full_name()).unwrap();
+    writeln!(out, "pub mod {}_traced {{", snake).unwrap();
+    writeln!(out, "    pub const SERVICE: &str = {:?};", service.full_name()).unwrap();
+    writeln!(out).unwrap();
+    write_client(&mut out, service, &snake);
+    writeln!(out).unwrap();
+    write_server(&mut out, service, &snake);
+    writeln!(out, "}}").unwrap();
+    out
+}
+
+/// Write the wrappers of `services` into `out_dir/{package}.traced.rs`, one file per package,
+/// to `include!` next to the tonic-build output.
+pub fn write_to(out_dir: impl AsRef<std::path::Path>, services: &[Service]) -> std::io::Result<()> {
+    let mut files: Vec<(&str, String)> = Vec::new();
+    for service in services {
+        let source = generate(service);
+        match files.iter_mut().find(|(package, _)| *package == service.package) {
+            Some((_, file)) => file.push_str(&source),
+            None => files.push((&service.package, source)),
+        }
+    }
+    for (package, source) in files {
+        let name = if package.is_empty() { "_" } else { package };
+        std::fs::write(out_dir.as_ref().join(format!("{}.traced.rs", name)), source)?;
+    }
+    Ok(())
+}
+
+fn write_client(out: &mut String, service: &Service, snake: &str) {
+    let client = format!("super::{}_client::{}Client", snake, service.rust_name);
+    writeln!(out, "    /// `{}Client` opening a client span per call, child of the current tracing span.", service.rust_name).unwrap();
+    writeln!(out, "    #[derive(Debug, Clone)]").unwrap();
+    writeln!(out, "    pub struct Traced{}Client<T> {{", service.rust_name).unwrap();
+    writeln!(out, "        inner: {}<T>,", client).unwrap();
+    writeln!(out, "    }}").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "    impl<T> Traced{}Client<T>", service.rust_name).unwrap();
+    writeln!(out, "    where").unwrap();
+    writeln!(out, "        T: tonic::client::GrpcService<tonic::body::BoxBody>,").unwrap();
+    writeln!(out, "        T::Error: Into<tonic::codegen::StdError>,").unwrap();
+    writeln!(out, "        T::ResponseBody: tonic::codegen::Body<Data = tonic::codegen::Bytes> + Send + 'static,").unwrap();
+    writeln!(out, "        <T::ResponseBody as tonic::codegen::Body>::Error: Into<tonic::codegen::StdError> + Send,").unwrap();
+    writeln!(out, "    {{").unwrap();
+    writeln!(out, "        pub fn new(inner: {}<T>) -> Self {{", client).unwrap();
+    writeln!(out, "            Traced{}Client {{ inner }}", service.rust_name).unwrap();
+    writeln!(out, "        }}").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "        pub fn into_inner(self) -> {}<T> {{", client).unwrap();
+    writeln!(out, "            self.inner").unwrap();
+    writeln!(out, "        }}").unwrap();
+    for method in &service.methods {
+        let request = match method.client_streaming {
+            true => format!("impl tonic::IntoStreamingRequest<Message = {}>", method.input_type),
+            false => format!("impl tonic::IntoRequest<{}>", method.input_type),
+        };
+        let into_request = match method.client_streaming {
+            true => "into_streaming_request",
+            false => "into_request",
+        };
+        writeln!(out).unwrap();
+        writeln!(out, "        pub async fn {}(", method.rust_name).unwrap();
+        writeln!(out, "            &mut self,").unwrap();
+        writeln!(out, "            request: {},", request).unwrap();
+        writeln!(out, "        ) -> Result<tonic::Response<{}>, tonic::Status> {{", response_type(method)).unwrap();
+        writeln!(out, "            let mut request = request.{}();", into_request).unwrap();
+        writeln!(out, "            let span = ::opentelemetry_tonic::__private::client_span(SERVICE, {:?});", method.name).unwrap();
+        writeln!(out, "            ::opentelemetry_tonic::__private::inject(&span, &mut request);").unwrap();
+        writeln!(out, "            ::opentelemetry_tonic::__private::traced_client(span, self.inner.{}(request)).await", method.rust_name).unwrap();
+        writeln!(out, "        }}").unwrap();
+    }
+    writeln!(out, "    }}").unwrap();
+}
+
+fn write_server(out: &mut String, service: &Service, snake: &str) {
+    let server_mod = format!("super::{}_server", snake);
+    let service_trait = format!("{}::{}", server_mod, service.rust_name);
+    writeln!(out, "    /// `{}` implementation opening a server span per method, parented on the client context.", service.rust_name).unwrap();
+    writeln!(out, "    #[derive(Debug)]").unwrap();
+    writeln!(out, "    pub struct Traced{}<T>(pub T);", service.rust_name).unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "    #[tonic::async_trait]").unwrap();
+    writeln!(out, "    impl<T: {}> {} for Traced{}<T> {{", service_trait, service_trait, service.rust_name).unwrap();
+    for method in &service.methods {
+        let request = match method.client_streaming {
+            true => format!("tonic::Streaming<{}>", method.input_type),
+            false => method.input_type.clone(),
+        };
+        let response = match method.server_streaming {
+            true => format!("Self::{}Stream", method.name),
+            false => method.output_type.clone(),
+        };
+        if method.server_streaming {
+            writeln!(out, "        type {}Stream = ::opentelemetry_tonic::TracedStream<T::{}Stream>;", method.name, method.name).unwrap();
+            writeln!(out).unwrap();
+        }
+        writeln!(out, "        async fn {}(", method.rust_name).unwrap();
+        writeln!(out, "            &self,").unwrap();
+        writeln!(out, "            request: tonic::Request<{}>,", request).unwrap();
+        writeln!(out, "        ) -> Result<tonic::Response<{}>, tonic::Status> {{", response).unwrap();
+        writeln!(out, "            let span = ::opentelemetry_tonic::__private::server_span(&request, SERVICE, {:?});", method.name).unwrap();
+        if method.server_streaming {
+            // the stream keeps the span open until it is done
+            writeln!(out, "            let response = ::opentelemetry_tonic::__private::traced(span.clone(), self.0.{}(request)).await?;", method.rust_name).unwrap();
+            writeln!(out, "            Ok(response.map(|stream| ::opentelemetry_tonic::TracedStream::sent_responses(stream).in_span(span)))").unwrap();
+        } else {
+            writeln!(out, "            ::opentelemetry_tonic::__private::traced(span, self.0.{}(request)).await", method.rust_name).unwrap();
+        }
+        writeln!(out, "        }}").unwrap();
+        writeln!(out).unwrap();
+    }
+    // drop the blank line after the last method
+    if !service.methods.is_empty() {
+        out.pop();
+    }
+    writeln!(out, "    }}").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "    pub fn traced_{}_server<T: {}>(inner: T) -> {}::{}Server<Traced{}<T>> {{", snake, service_trait, server_mod, service.rust_name, service.rust_name).unwrap();
+    writeln!(out, "        {}::{}Server::new(Traced{}(inner))", server_mod, service.rust_name, service.rust_name).unwrap();
+    writeln!(out, "    }}").unwrap();
+}
+
+fn response_type(method: &Method) -> String {
+    match method.server_streaming {
+        true => format!("tonic::codec::Streaming<{}>", method.output_type),
+        false => method.output_type.clone(),
+    }
+}
+
+// module name tonic-build derives from the service name, "HealthCheck" -> "health_check"
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for (i, c) in name.char_indices() {
+        if c.is_ascii_uppercase() {
+            if i > 0 {
+                snake.push('_');
+            }
+            snake.push(c.to_ascii_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{generate, snake_case, type_path, Method, Service};
+
+    fn greeter() -> Service {
+        let method = |name: &str, rust_name: &str, client_streaming, server_streaming| Method {
+            name: name.to_string(),
+            rust_name: rust_name.to_string(),
+            input_type: "super::HelloRequest".to_string(),
+            output_type: "super::HelloReply".to_string(),
+            client_streaming,
+            server_streaming,
+        };
+        Service {
+            package: "helloworld".to_string(),
+            name: "Greeter".to_string(),
+            rust_name: "Greeter".to_string(),
+            methods: vec![
+                method("SayHello", "say_hello", false, false),
+                method("Chat", "chat", true, true),
+            ],
+        }
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(greeter().full_name(), "helloworld.Greeter");
+        assert_eq!(snake_case("Greeter"), "greeter");
+        assert_eq!(snake_case("HealthCheck"), "health_check");
+        assert_eq!(type_path("HelloRequest"), "super::HelloRequest");
+        assert_eq!(type_path("()"), "()");
+        assert_eq!(type_path("::prost_types::Timestamp"), "::prost_types::Timestamp");
+    }
+
+    #[test]
+    fn wrappers() {
+        let source = generate(&greeter());
+        for expected in [
+            "pub mod greeter_traced {",
+            "pub const SERVICE: &str = \"helloworld.Greeter\";",
+            "pub struct TracedGreeterClient<T> {",
+            "request: impl tonic::IntoRequest<super::HelloRequest>,",
+            "request: impl tonic::IntoStreamingRequest<Message = super::HelloRequest>,",
+            ") -> Result<tonic::Response<tonic::codec::Streaming<super::HelloReply>>, tonic::Status> {",
+            "::opentelemetry_tonic::__private::client_span(SERVICE, \"SayHello\");",
+            "impl<T: super::greeter_server::Greeter> super::greeter_server::Greeter for TracedGreeter<T> {",
+            "type ChatStream = ::opentelemetry_tonic::TracedStream<T::ChatStream>;",
+            "::opentelemetry_tonic::TracedStream::sent_responses(stream).in_span(span)",
+            "request: tonic::Request<tonic::Streaming<super::HelloRequest>>,",
+            "::opentelemetry_tonic::__private::server_span(&request, SERVICE, \"Chat\");",
+            "pub fn traced_greeter_server<T: super::greeter_server::Greeter>(inner: T)",
+        ] {
+            assert!(source.contains(expected), "missing {:?} in\n{}", expected, source);
+        }
+    }
+}
@@ -0,0 +1,358 @@
+use std::task::{Context as TaskContext, Poll};
+
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::Context;
+
+use tower_layer::Layer;
+use tower_service::Service;
+
+// extend tracing::Span with context()
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::encoding::{EncodingInjector, Encodings};
+use crate::filter::MethodFilter;
+use crate::grpc_trace_bin::GrpcTraceBinPropagator;
+use crate::headers::with_metadata;
+use crate::propagator::Propagator;
+use crate::response::{deadline, ResponseFuture, TracedBody};
+use crate::semconv::{rpc_span, RpcKind};
+
+
+/// Where the client layer takes the context to inject from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ContextSource {
+    /// The current `tracing::Span`, like `tracing_current_span_to_req`.
+    #[default]
+    TracingSpan,
+    /// The current thread-bound OTel `Context`, like `otel_thread_cx_to_req`.
+    OtelContext,
+}
+
+impl ContextSource {
+    pub(crate) fn current(self) -> Context {
+        match self {
+            ContextSource::TracingSpan => tracing::Span::current().context(),
+            ContextSource::OtelContext => Context::current(),
+        }
+    }
+}
+
+
+/// Client `Layer` opening a client span per RPC and injecting its context into the request.
+/// The client span is a child of the current `tracing::Span` or OTel `Context`, see [`ContextSource`].
+///
+/// pre-requisite, unless [`with_propagator`](Self::with_propagator) is used:
+/// global::set_text_map_propagator(TraceContextPropagator::new());
+///
+/// ```ignore
+/// let channel = ServiceBuilder::new()
+///     .layer(TraceInjectLayer::new())
+///     .service(channel);
+/// let client = GreeterClient::new(channel);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct TraceInjectLayer {
+    source: ContextSource,
+    propagator: Propagator,
+    filter: Option<MethodFilter>,
+    encodings: Encodings,
+    grpc_trace_bin: bool,
+}
+
+impl TraceInjectLayer {
+    /// Inject the context of the current `tracing::Span`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inject the current thread-bound OTel `Context` instead of the tracing span.
+    pub fn from_otel_context() -> Self {
+        Self::with_source(ContextSource::OtelContext)
+    }
+
+    pub fn with_source(source: ContextSource) -> Self {
+        TraceInjectLayer {
+            source,
+            ..Self::default()
+        }
+    }
+
+    /// Inject with this propagator instead of the global one.
+    pub fn with_propagator<P>(mut self, propagator: P) -> Self
+    where
+        P: TextMapPropagator + Send + Sync + 'static,
+    {
+        self.propagator = Propagator::new(propagator);
+        self
+    }
+
+    /// Open spans only for the methods traced by `filter`,
+    /// the context of the caller is still injected for the others.
+    pub fn with_filter(mut self, filter: MethodFilter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Encode the values of these keys, e.g. percent-encoded baggage, instead of sending them as is.
+    /// The server needs the same `Encodings`, see [`TraceExtractLayer::with_encodings`](crate::TraceExtractLayer::with_encodings).
+    pub fn with_encodings(mut self, encodings: Encodings) -> Self {
+        self.encodings = encodings;
+        self
+    }
+
+    /// Also send the span context in the binary `grpc-trace-bin` entry,
+    /// for servers using gRPC's OpenCensus tracing, see [`grpc_trace_bin`](crate::grpc_trace_bin).
+    pub fn with_grpc_trace_bin(mut self) -> Self {
+        self.grpc_trace_bin = true;
+        self
+    }
+}
+
+impl<S> Layer<S> for TraceInjectLayer {
+    type Service = TraceInjectService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TraceInjectService {
+            inner,
+            source: self.source,
+            propagator: self.propagator.clone(),
+            filter: self.filter.clone(),
+            encodings: self.encodings.clone(),
+            grpc_trace_bin: self.grpc_trace_bin,
+        }
+    }
+}
+
+
+/// Service created by [`TraceInjectLayer`].
+#[derive(Debug, Clone)]
+pub struct TraceInjectService<S> {
+    inner: S,
+    source: ContextSource,
+    propagator: Propagator,
+    filter: Option<MethodFilter>,
+    encodings: Encodings,
+    grpc_trace_bin: bool,
+}
+
+impl<S, B, ResBody> Service<http::Request<B>> for TraceInjectService<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<ResBody>>,
+{
+    type Response = http::Response<TracedBody<ResBody>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: http::Request<B>) -> Self::Future {
+        if let Some(filter) = &self.filter {
+            if !filter.traces(request.uri().path()) {
+                self.inject(&self.source.current(), request.headers_mut());
+                return ResponseFuture::new(self.inner.call(request), tracing::Span::none(), RpcKind::Client);
+            }
+        }
+
+        let span = rpc_span(RpcKind::Client, &request);
+        if self.source == ContextSource::OtelContext {
+            span.set_parent(Context::current());
+        }
+        self.inject(&span.context(), request.headers_mut());
+
+        let deadline = deadline(request.headers());
+        ResponseFuture::new(span.in_scope(|| self.inner.call(request)), span, RpcKind::Client).with_deadline(deadline)
+    }
+}
+
+
+impl<S> TraceInjectService<S> {
+    fn inject(&self, cx: &Context, headers: &mut http::HeaderMap) {
+        with_metadata(headers, |metadata| {
+            let mut injector = EncodingInjector::new(metadata, &self.encodings);
+            self.propagator.with(|propagator| propagator.inject_context(cx, &mut injector));
+            // rejected entries are logged with tracing::warn!
+            let _report = injector.into_report();
+            if self.grpc_trace_bin {
+                GrpcTraceBinPropagator::new().inject_context(cx, metadata);
+            }
+        });
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use http_body::Body;
+    use opentelemetry::baggage::BaggageExt;
+    use opentelemetry::{global, Context, KeyValue, Value};
+    use opentelemetry::sdk::propagation::{BaggagePropagator, TraceContextPropagator};
+    use opentelemetry::trace::{Span, SpanKind, Status, Tracer, TracerProvider as _, TraceContextExt};
+    use tonic::body::{empty_body, BoxBody};
+    use tonic::metadata::MetadataMap;
+    use tower::{service_fn, ServiceBuilder, ServiceExt};
+    use tracing::Instrument;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    use super::TraceInjectLayer;
+    use crate::testing::{attribute, SpanRecorder, TenantPropagator, TrailersBody};
+    use crate::grpc_trace_bin::GrpcTraceBinPropagator;
+    use crate::{Encoding, Encodings};
+
+    // echo request headers back, with a grpc-status for trailers-only responses
+    async fn echo_headers(request: http::Request<()>) -> Result<http::Response<BoxBody>, Infallible> {
+        let mut response = http::Response::new(empty_body());
+        *response.headers_mut() = request.headers().clone();
+        response.headers_mut().insert("grpc-status", "0".parse().unwrap());
+        Ok(response)
+    }
+
+    fn grpc_request() -> http::Request<()> {
+        http::Request::builder()
+            .uri("/helloworld.Greeter/SayHello")
+            .body(())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn inject_tracing_span() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let recorder = SpanRecorder::default();
+        let (_provider, subscriber) = recorder.subscriber();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let span = tracing::info_span!("caller");
+        let trace_id = span.context().span().span_context().trace_id();
+        let response = ServiceBuilder::new()
+            .layer(TraceInjectLayer::new())
+            .service(service_fn(echo_headers))
+            .oneshot(grpc_request())
+            .instrument(span.clone())
+            .await
+            .unwrap();
+        let traceparent = response.headers()["traceparent"].to_str().unwrap().to_string();
+        drop(response);
+        drop(span);
+
+        let client_span = recorder.span("helloworld.Greeter/SayHello");
+        assert_eq!(client_span.span_kind, SpanKind::Client);
+        assert_eq!(client_span.span_context.trace_id(), trace_id);
+        assert!(traceparent.contains(&client_span.span_context.span_id().to_string()));
+        assert_eq!(attribute(&client_span, "rpc.method"), Some(Value::from("SayHello")));
+        assert_eq!(attribute(&client_span, "rpc.grpc.status_code"), Some(Value::I64(0)));
+    }
+
+    #[tokio::test]
+    async fn error_status_from_trailers() {
+        let recorder = SpanRecorder::default();
+        let (_provider, subscriber) = recorder.subscriber();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let mut trailers = http::HeaderMap::new();
+        trailers.insert("grpc-status", "5".parse().unwrap());
+        trailers.insert("grpc-message", "no%20such%20user".parse().unwrap());
+
+        let mut response = ServiceBuilder::new()
+            .layer(TraceInjectLayer::new())
+            .service(service_fn(move |_request: http::Request<()>| {
+                let body = TrailersBody(Some(trailers.clone()));
+                async move { Ok::<_, Infallible>(http::Response::new(body)) }
+            }))
+            .oneshot(grpc_request())
+            .await
+            .unwrap();
+        response.body_mut().trailers().await.unwrap();
+        drop(response);
+
+        // every non-OK code is an error on the client side
+        let span = recorder.span("helloworld.Greeter/SayHello");
+        assert_eq!(attribute(&span, "rpc.grpc.status_code"), Some(Value::I64(5)));
+        assert_eq!(span.status, Status::error("no such user"));
+    }
+
+    #[tokio::test]
+    async fn inject_otel_context() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let recorder = SpanRecorder::default();
+        let (provider, subscriber) = recorder.subscriber();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let span = provider.tracer("test").start("caller");
+        let trace_id = span.span_context().trace_id();
+        let _cx = Context::current_with_span(span).attach();
+
+        let response = ServiceBuilder::new()
+            .layer(TraceInjectLayer::from_otel_context())
+            .service(service_fn(echo_headers))
+            .oneshot(grpc_request())
+            .await
+            .unwrap();
+
+        let traceparent = response.headers()["traceparent"].to_str().unwrap();
+        assert!(traceparent.contains(&trace_id.to_string()));
+    }
+
+    #[tokio::test]
+    async fn explicit_propagator() {
+        let recorder = SpanRecorder::default();
+        let (_provider, subscriber) = recorder.subscriber();
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let _cx = Context::current_with_baggage(vec![KeyValue::new("tenant.id", "acme")]).attach();
+
+        let response = ServiceBuilder::new()
+            .layer(TraceInjectLayer::from_otel_context().with_propagator(BaggagePropagator::new()))
+            .service(service_fn(echo_headers))
+            .oneshot(grpc_request())
+            .await
+            .unwrap();
+
+        assert_eq!(response.headers()["baggage"], "tenant.id=acme");
+        assert!(!response.headers().contains_key("traceparent"));
+    }
+
+    #[tokio::test]
+    async fn grpc_trace_bin() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let recorder = SpanRecorder::default();
+        let (_provider, subscriber) = recorder.subscriber();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let response = ServiceBuilder::new()
+            .layer(TraceInjectLayer::new().with_grpc_trace_bin())
+            .service(service_fn(echo_headers))
+            .oneshot(grpc_request())
+            .await
+            .unwrap();
+        let metadata = MetadataMap::from_headers(response.headers().clone());
+        drop(response);
+
+        let span = recorder.span("helloworld.Greeter/SayHello");
+        let sent = GrpcTraceBinPropagator::new().extract(&metadata);
+        assert_eq!(sent.span().span_context().span_id(), span.span_context.span_id());
+        assert!(metadata.get("traceparent").is_some());
+    }
+
+    #[tokio::test]
+    async fn encodings() {
+        let recorder = SpanRecorder::default();
+        let (_provider, subscriber) = recorder.subscriber();
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let _cx = Context::current_with_baggage(vec![KeyValue::new("tenant", "Zürich NY")]).attach();
+
+        let response = ServiceBuilder::new()
+            .layer(
+                TraceInjectLayer::from_otel_context()
+                    .with_propagator(TenantPropagator::new())
+                    .with_encodings(Encodings::new().with_key("x-tenant", Encoding::Percent)),
+            )
+            .service(service_fn(echo_headers))
+            .oneshot(grpc_request())
+            .await
+            .unwrap();
+
+        assert_eq!(response.headers()["x-tenant"], "Z%C3%BCrich%20NY");
+    }
+}
@@ -0,0 +1,267 @@
+//! Log correlation: `trace_id` & `span_id` of the OTel span around each event, in the `tracing_subscriber::fmt` output.
+
+use std::fmt;
+
+use opentelemetry::trace::{SpanId, TraceContextExt, TraceId};
+use tracing::{Event, Subscriber};
+use tracing_opentelemetry::OtelData;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::registry::{LookupSpan, SpanRef};
+
+
+/// Trace & span id `span` is exported with, `None` without the `tracing_opentelemetry` layer.
+/// The trace id follows the parent set with `set_parent`, e.g. by `tracing_parent_span_from_req`.
+pub fn trace_ids<'a, S: LookupSpan<'a>>(span: &SpanRef<'a, S>) -> Option<(TraceId, SpanId)> {
+    let extensions = span.extensions();
+    let data = extensions.get::<OtelData>()?;
+    // same choice as tracing_opentelemetry when the span is built
+    let trace_id = match data.parent_cx.has_active_span() {
+        true => data.parent_cx.span().span_context().trace_id(),
+        false => data.builder.trace_id?,
+    };
+    Some((trace_id, data.builder.span_id?))
+}
+
+
+/// `FormatEvent` adding the `trace_id` & `span_id` of the current span to the events formatted by `inner`,
+/// e.g. the events of the handlers inside the span of `TraceExtractLayer` or `#[traced_rpc]`.
+/// Events outside spans are formatted as is.
+///
+/// ```ignore
+/// tracing_subscriber::registry()
+///     .with(tracing_opentelemetry::layer().with_tracer(tracer))
+///     .with(tracing_subscriber::fmt::layer().event_format(WithTraceIds::new(format())))
+///     // or in JSON logs, with tracing-subscriber's `json` feature
+///     .with(tracing_subscriber::fmt::layer().json().event_format(WithTraceIds::json(format().json())))
+///     .init();
+/// ```
+#[derive(Debug, Clone)]
+pub struct WithTraceIds<F> {
+    inner: F,
+    json: bool,
+}
+
+impl<F> WithTraceIds<F> {
+    /// `trace_id=… span_id=… ` before the event.
+    pub fn new(inner: F) -> Self {
+        WithTraceIds { inner, json: false }
+    }
+
+    /// `"trace_id":"…","span_id":"…"` as first fields of the JSON object written by `inner`,
+    /// e.g. `format().json()`, inserted while it is written. Lines which are not a JSON object are written as is.
+    pub fn json(inner: F) -> Self {
+        WithTraceIds { inner, json: true }
+    }
+}
+
+impl<S, N, F> FormatEvent<S, N> for WithTraceIds<F>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+    F: FormatEvent<S, N>,
+{
+    fn format_event(&self, ctx: &FmtContext<'_, S, N>, mut writer: Writer<'_>, event: &Event<'_>) -> fmt::Result {
+        let ids = ctx
+            .event_scope()
+            .and_then(|mut scope| scope.find_map(|span| trace_ids(&span)));
+        let Some((trace_id, span_id)) = ids else {
+            return self.inner.format_event(ctx, writer, event);
+        };
+
+        if !self.json {
+            write!(writer, "trace_id={} span_id={} ", trace_id, span_id)?;
+            return self.inner.format_event(ctx, writer, event);
+        }
+        let mut ids_first = JsonIds {
+            writer,
+            ids: (trace_id, span_id),
+            state: JsonState::Start,
+        };
+        self.inner.format_event(ctx, Writer::new(&mut ids_first), event)
+    }
+}
+
+
+// writer adding the ids after the opening brace of the JSON object written through it
+struct JsonIds<'a> {
+    writer: Writer<'a>,
+    ids: (TraceId, SpanId),
+    state: JsonState,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum JsonState {
+    Start,
+    // ids written, the separator depends on whether the object has fields of its own
+    Ids,
+    Done,
+}
+
+impl fmt::Write for JsonIds<'_> {
+    fn write_str(&mut self, mut s: &str) -> fmt::Result {
+        if self.state == JsonState::Start && !s.is_empty() {
+            match s.strip_prefix('{') {
+                Some(rest) => {
+                    let (trace_id, span_id) = self.ids;
+                    write!(self.writer, "{{\"trace_id\":\"{}\",\"span_id\":\"{}\"", trace_id, span_id)?;
+                    self.state = JsonState::Ids;
+                    s = rest;
+                }
+                None => self.state = JsonState::Done,
+            }
+        }
+        if self.state == JsonState::Ids && !s.is_empty() {
+            if !s.starts_with('}') {
+                self.writer.write_char(',')?;
+            }
+            self.state = JsonState::Done;
+        }
+        self.writer.write_str(s)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Write as _;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    use opentelemetry::sdk::propagation::TraceContextPropagator;
+    use opentelemetry::sdk::trace::TracerProvider;
+    use opentelemetry::trace::{SpanId, TraceContextExt, TraceId, TracerProvider as _};
+    use serde_json::Value;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+    use tracing_subscriber::fmt::format::{format, Writer};
+    use tracing_subscriber::fmt::MakeWriter;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::{JsonIds, JsonState, WithTraceIds};
+    use crate::tracing_parent_span_from_req_with;
+
+    #[derive(Clone, Default)]
+    struct Lines(Arc<Mutex<Vec<u8>>>);
+
+    impl Lines {
+        fn lines(&self) -> Vec<String> {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap().lines().map(str::to_string).collect()
+        }
+    }
+
+    impl io::Write for Lines {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Lines {
+        type Writer = Lines;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    fn ids(span: &tracing::Span) -> String {
+        let cx = span.context();
+        let span_context = cx.span().span_context().clone();
+        format!("trace_id={} span_id={}", span_context.trace_id(), span_context.span_id())
+    }
+
+    #[test]
+    fn text() {
+        let provider = TracerProvider::builder().build();
+        let lines = Lines::default();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")))
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .event_format(WithTraceIds::new(format().without_time().with_ansi(false)))
+                    .with_writer(lines.clone()),
+            );
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let mut request = tonic::Request::new(());
+        request
+            .metadata_mut()
+            .insert("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".parse().unwrap());
+
+        tracing::info!("outside");
+        let rpc = tracing::info_span!("rpc");
+        let db = rpc.in_scope(|| {
+            tracing_parent_span_from_req_with(&request, &TraceContextPropagator::new());
+            tracing::info!("handling");
+            let db = tracing::info_span!("db");
+            db.in_scope(|| tracing::info!("query"));
+            db
+        });
+
+        let lines = lines.lines();
+        assert!(!lines[0].contains("trace_id="), "{}", lines[0]);
+        assert!(lines[1].starts_with(&format!("{}  INFO rpc: ", ids(&rpc))), "{}", lines[1]);
+        assert!(lines[1].starts_with("trace_id=4bf92f3577b34da6a3ce929d0e0e4736 "));
+        assert!(lines[2].starts_with(&format!("{}  INFO rpc:db: ", ids(&db))), "{}", lines[2]);
+        assert!(lines[2].starts_with("trace_id=4bf92f3577b34da6a3ce929d0e0e4736 "));
+    }
+
+    #[test]
+    fn json() {
+        let provider = TracerProvider::builder().build();
+        let lines = Lines::default();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")))
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .json()
+                    .event_format(WithTraceIds::json(format().json().without_time()))
+                    .with_writer(lines.clone()),
+            );
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        tracing::info!("outside");
+        let rpc = tracing::info_span!("rpc", method = "SayHello");
+        rpc.in_scope(|| tracing::info!(user.id = 42, "handling"));
+
+        let cx = rpc.context();
+        let span_context = cx.span().span_context().clone();
+        let raw = lines.lines();
+        let lines: Vec<Value> = raw.iter().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines[0]["fields"]["message"], "outside");
+        assert!(lines[0].get("trace_id").is_none());
+
+        assert_eq!(lines[1]["fields"]["message"], "handling");
+        assert_eq!(lines[1]["fields"]["user.id"], 42);
+        assert_eq!(lines[1]["span"]["method"], "SayHello");
+        assert_eq!(lines[1]["trace_id"], span_context.trace_id().to_string());
+        assert_eq!(lines[1]["span_id"], span_context.span_id().to_string());
+        // before the fields of the inner format
+        let ids = format!("{{\"trace_id\":\"{}\",\"span_id\":\"{}\",\"level\":", span_context.trace_id(), span_context.span_id());
+        assert!(raw[1].starts_with(&ids), "{}", raw[1]);
+    }
+
+    #[test]
+    fn json_ids_first() {
+        let write = |chunks: &[&str]| {
+            let mut line = String::new();
+            let mut ids_first = JsonIds {
+                writer: Writer::new(&mut line),
+                ids: (TraceId::from_bytes(1u128.to_be_bytes()), SpanId::from_bytes(2u64.to_be_bytes())),
+                state: JsonState::Start,
+            };
+            for chunk in chunks {
+                ids_first.write_str(chunk).unwrap();
+            }
+            line
+        };
+        let ids = "\"trace_id\":\"00000000000000000000000000000001\",\"span_id\":\"0000000000000002\"";
+        assert_eq!(write(&["{\"level\":\"INFO\"}\n"]), format!("{{{},\"level\":\"INFO\"}}\n", ids));
+        assert_eq!(write(&["", "{", "", "\"level\":", "\"INFO\"}"]), format!("{{{},\"level\":\"INFO\"}}", ids));
+        assert_eq!(write(&["{", "}\n"]), format!("{{{}}}\n", ids));
+        assert_eq!(write(&["INFO {not json}"]), "INFO {not json}");
+    }
+}
@@ -0,0 +1,281 @@
+use std::str::FromStr;
+
+use opentelemetry::propagation::{Extractor, Injector};
+
+use tonic::metadata::{Ascii, Binary, KeyAndValueRef, KeyRef, MetadataKey, MetadataMap, MetadataValue};
+
+use crate::report::{reject, InjectionError, InjectionReport};
+
+
+/// How the value of a propagator entry is carried in ascii gRPC metadata.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    /// As is, like `MetadataInjector`: values the metadata rejects are dropped.
+    #[default]
+    Plain,
+    /// Percent-encode `%`, spaces, control characters and non-ASCII bytes, decoded on extraction.
+    /// For propagators writing their values as is, e.g. a custom tenant header. Not for W3C `baggage`,
+    /// which `BaggagePropagator` already percent-encodes: its escapes would be encoded twice, and those
+    /// of other clients, e.g. `%2C`, decoded before the propagator splits the members.
+    Percent,
+    /// As is when the metadata accepts the value, else as bytes under the `<key>-bin` key,
+    /// read back under `<key>` on extraction.
+    BinaryFallback,
+}
+
+
+/// Encoding per propagator key, for [`EncodingInjector`] & [`EncodingExtractor`].
+/// Both sides need the same configuration.
+///
+/// e.g. Encodings::new().with_key("x-tenant", Encoding::Percent)
+#[derive(Debug, Clone, Default)]
+pub struct Encodings {
+    default: Encoding,
+    keys: Vec<(String, Encoding)>,
+}
+
+impl Encodings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Encoding of the keys without one of their own, `Encoding::Plain` by default.
+    pub fn with_default(mut self, encoding: Encoding) -> Self {
+        self.default = encoding;
+        self
+    }
+
+    pub fn with_key(mut self, key: impl Into<String>, encoding: Encoding) -> Self {
+        let key = key.into().to_ascii_lowercase();
+        self.keys.retain(|(configured, _)| *configured != key);
+        self.keys.push((key, encoding));
+        self
+    }
+
+    pub fn encoding(&self, key: &str) -> Encoding {
+        self.keys
+            .iter()
+            .find(|(configured, _)| configured.eq_ignore_ascii_case(key))
+            .map_or(self.default, |(_, encoding)| *encoding)
+    }
+}
+
+
+/// `Injector` encoding values per [`Encodings`], so payloads the ascii metadata rejects are still sent.
+/// Entries which can't be sent at all, e.g. invalid keys, are reported like [`CheckedMetadataInjector`](crate::CheckedMetadataInjector).
+pub struct EncodingInjector<'a> {
+    metadata: &'a mut MetadataMap,
+    encodings: &'a Encodings,
+    report: InjectionReport,
+}
+
+impl<'a> EncodingInjector<'a> {
+    pub fn new(metadata: &'a mut MetadataMap, encodings: &'a Encodings) -> Self {
+        EncodingInjector {
+            metadata,
+            encodings,
+            report: InjectionReport::default(),
+        }
+    }
+
+    pub fn into_report(self) -> InjectionReport {
+        self.report
+    }
+}
+
+impl<'a> Injector for EncodingInjector<'a> {
+    /// Set a key and value in the MetadataMap, encoded per the key's `Encoding`.
+    fn set(&mut self, key: &str, value: String) {
+        let Ok(metadata_key) = MetadataKey::<Ascii>::from_str(key) else {
+            return reject(&mut self.report, InjectionError::InvalidKey { key: key.to_string() });
+        };
+        let encoding = self.encodings.encoding(key);
+        let value = match encoding {
+            Encoding::Percent => percent_encode(&value),
+            Encoding::Plain | Encoding::BinaryFallback => value,
+        };
+        if let Ok(val) = value.parse::<MetadataValue<Ascii>>() {
+            self.metadata.insert(metadata_key, val);
+            return;
+        }
+        match encoding {
+            Encoding::BinaryFallback => match MetadataKey::<Binary>::from_str(&format!("{}-bin", key)) {
+                Ok(bin_key) => {
+                    self.metadata.insert_bin(bin_key, MetadataValue::from_bytes(value.as_bytes()));
+                }
+                Err(_) => reject(&mut self.report, InjectionError::InvalidKey { key: key.to_string() }),
+            },
+            _ => reject(&mut self.report, InjectionError::InvalidValue { key: key.to_string(), value }),
+        }
+    }
+}
+
+
+/// `Extractor` decoding what [`EncodingInjector`] sent with the same [`Encodings`].
+pub struct EncodingExtractor<'a> {
+    metadata: &'a MetadataMap,
+    encodings: &'a Encodings,
+    // values decoded from percent-encoding or `-bin` entries, by propagator key, `get` borrows from here
+    decoded: Vec<(String, String)>,
+}
+
+impl<'a> EncodingExtractor<'a> {
+    pub fn new(metadata: &'a MetadataMap, encodings: &'a Encodings) -> Self {
+        let mut decoded: Vec<(String, String)> = Vec::new();
+        for entry in metadata.iter() {
+            let (key, value) = match entry {
+                KeyAndValueRef::Ascii(key, value) if encodings.encoding(key.as_str()) == Encoding::Percent => {
+                    let value = value.to_str().ok().and_then(percent_decode);
+                    (key.as_str(), value)
+                }
+                KeyAndValueRef::Binary(key, value) => {
+                    let key = &key.as_str()[..key.as_str().len() - 4];
+                    if encodings.encoding(key) != Encoding::BinaryFallback {
+                        continue;
+                    }
+                    let value = value.to_bytes().ok().and_then(|bytes| String::from_utf8(bytes.to_vec()).ok());
+                    (key, value)
+                }
+                KeyAndValueRef::Ascii(..) => continue,
+            };
+            if let Some(value) = value {
+                if !decoded.iter().any(|(decoded_key, _)| decoded_key == key) {
+                    decoded.push((key.to_string(), value));
+                }
+            }
+        }
+        EncodingExtractor { metadata, encodings, decoded }
+    }
+}
+
+impl<'a> Extractor for EncodingExtractor<'a> {
+    /// Get a value for a key from the MetadataMap, decoded per the key's `Encoding`.
+    /// If the value can't be converted to &str, returns None
+    fn get(&self, key: &str) -> Option<&str> {
+        let decoded = || {
+            self.decoded
+                .iter()
+                .find(|(decoded_key, _)| decoded_key.eq_ignore_ascii_case(key))
+                .map(|(_, value)| value.as_str())
+        };
+        match self.encodings.encoding(key) {
+            Encoding::Plain => self.metadata.get(key).and_then(|value| value.to_str().ok()),
+            Encoding::Percent => decoded(),
+            Encoding::BinaryFallback => {
+                self.metadata.get(key).and_then(|value| value.to_str().ok()).or_else(decoded)
+            }
+        }
+    }
+
+    /// Collect all the keys from the MetadataMap, `<key>` for a `<key>-bin` fallback entry.
+    fn keys(&self) -> Vec<&str> {
+        let mut keys: Vec<&str> = Vec::new();
+        for key in self.metadata.keys() {
+            let key = match key {
+                KeyRef::Ascii(key) => key.as_str(),
+                KeyRef::Binary(key) => {
+                    let base = &key.as_str()[..key.as_str().len() - 4];
+                    match self.encodings.encoding(base) {
+                        Encoding::BinaryFallback => base,
+                        _ => key.as_str(),
+                    }
+                }
+            };
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+        keys
+    }
+}
+
+
+fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'%' => encoded.push_str("%25"),
+            0x21..=0x7e => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+// malformed escapes are kept as is, None if the result is not UTF-8
+fn percent_decode(value: &str) -> Option<String> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| bytes.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::propagation::{Extractor, Injector};
+    use tonic::metadata::MetadataMap;
+
+    use super::{percent_decode, percent_encode, Encoding, EncodingExtractor, EncodingInjector, Encodings};
+
+    #[test]
+    fn percent() {
+        assert_eq!(percent_encode("city=Zürich; 100%"), "city=Z%C3%BCrich;%20100%25");
+        assert_eq!(percent_decode("city=Z%C3%BCrich;%20100%25").unwrap(), "city=Zürich; 100%");
+        assert_eq!(percent_decode("100% %zz %4").unwrap(), "100% %zz %4");
+        assert_eq!(percent_decode("%FF"), None);
+    }
+
+    #[test]
+    fn round_trip() {
+        let encodings = Encodings::new()
+            .with_key("x-tenant", Encoding::Percent)
+            .with_key("X-Payload", Encoding::BinaryFallback);
+        let entries = [
+            ("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+            ("x-tenant", "Zürich\nNY"),
+            ("x-payload", "line\nbreak"),
+            ("x-other", "no\nfallback"),
+        ];
+
+        let mut metadata = MetadataMap::new();
+        let mut injector = EncodingInjector::new(&mut metadata, &encodings);
+        for (key, value) in entries {
+            injector.set(key, value.to_string());
+        }
+        injector.set("x-short", "fits".to_string());
+        let report = injector.into_report();
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].key(), "x-other");
+
+        assert_eq!(metadata.get("x-tenant").unwrap(), "Z%C3%BCrich%0ANY");
+        assert!(metadata.get_bin("x-payload-bin").is_some());
+
+        let extractor = EncodingExtractor::new(&metadata, &encodings);
+        for (key, value) in &entries[..3] {
+            assert_eq!(extractor.get(key), Some(*value));
+        }
+        assert_eq!(extractor.get("x-other"), None);
+        assert_eq!(extractor.get("x-short"), Some("fits"));
+        let mut keys = extractor.keys();
+        keys.sort_unstable();
+        assert_eq!(keys, ["traceparent", "x-payload", "x-short", "x-tenant"]);
+    }
+}
@@ -0,0 +1,227 @@
+use std::fmt;
+use std::sync::Arc;
+
+use opentelemetry::sdk::trace::{Sampler, ShouldSample};
+use opentelemetry::trace::{Link, OrderMap, SamplingResult, SpanKind, TraceId};
+use opentelemetry::{Context, InstrumentationLibrary, Key, Value};
+
+use crate::semconv::{RPC_METHOD, RPC_SERVICE};
+
+
+/// Which RPCs the client and server layers open a span for, decided on the gRPC method
+/// "package.Service/Method" (the span name).
+///
+/// ```ignore
+/// TraceExtractLayer::new().with_filter(MethodFilter::exclude([
+///     "grpc.health.v1.Health/*",
+///     "grpc.reflection.*",
+/// ]))
+/// ```
+#[derive(Clone)]
+pub struct MethodFilter(Arc<dyn Fn(&str) -> bool + Send + Sync>);
+
+impl MethodFilter {
+    /// Trace the methods for which `predicate` returns true.
+    pub fn new<F>(predicate: F) -> Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        MethodFilter(Arc::new(predicate))
+    }
+
+    /// Trace every method but the ones matching one of `globs`, see [`glob_match`].
+    pub fn exclude<I>(globs: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        let globs: Vec<String> = globs.into_iter().map(Into::into).collect();
+        Self::new(move |method| !globs.iter().any(|glob| glob_match(glob, method)))
+    }
+
+    /// Trace only the methods matching one of `globs`, see [`glob_match`].
+    pub fn include<I>(globs: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        let globs: Vec<String> = globs.into_iter().map(Into::into).collect();
+        Self::new(move |method| globs.iter().any(|glob| glob_match(glob, method)))
+    }
+
+    /// Whether the request with this uri path is traced.
+    pub fn traces(&self, path: &str) -> bool {
+        (self.0)(path.strip_prefix('/').unwrap_or(path))
+    }
+}
+
+impl fmt::Debug for MethodFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("MethodFilter")
+    }
+}
+
+
+/// Sampler choosing the sampler of the first rule matching the gRPC method, for spans created
+/// by the layers or carrying `rpc.service` & `rpc.method` attributes.
+/// Other spans, and methods without a matching rule, go to the default sampler.
+///
+/// ```ignore
+/// let sampler = MethodSampler::new(Sampler::TraceIdRatioBased(0.1))
+///     .with_rule("*/Watch", Sampler::TraceIdRatioBased(0.01))
+///     .with_rule("shop.Cart/Checkout", Sampler::AlwaysOn);
+/// let provider = TracerProvider::builder()
+///     .with_config(trace::config().with_sampler(Sampler::ParentBased(Box::new(sampler))))
+///     .build();
+/// ```
+#[derive(Debug, Clone)]
+pub struct MethodSampler {
+    rules: Vec<(String, Sampler)>,
+    default: Sampler,
+}
+
+impl MethodSampler {
+    pub fn new(default: Sampler) -> Self {
+        MethodSampler {
+            rules: Vec::new(),
+            default,
+        }
+    }
+
+    /// Sample the methods matching `glob` with `sampler`, see [`glob_match`].
+    pub fn with_rule(mut self, glob: impl Into<String>, sampler: Sampler) -> Self {
+        self.rules.push((glob.into(), sampler));
+        self
+    }
+
+    fn sampler(&self, name: &str, attributes: &OrderMap<Key, Value>) -> &Sampler {
+        let method = match (attributes.get(&Key::new(RPC_SERVICE)), attributes.get(&Key::new(RPC_METHOD))) {
+            (Some(service), Some(method)) => format!("{}/{}", service.as_str(), method.as_str()),
+            _ => name.to_string(),
+        };
+        self.rules
+            .iter()
+            .find(|(glob, _)| glob_match(glob, &method))
+            .map_or(&self.default, |(_, sampler)| sampler)
+    }
+}
+
+impl ShouldSample for MethodSampler {
+    fn should_sample(
+        &self,
+        parent_context: Option<&Context>,
+        trace_id: TraceId,
+        name: &str,
+        span_kind: &SpanKind,
+        attributes: &OrderMap<Key, Value>,
+        links: &[Link],
+        instrumentation_library: &InstrumentationLibrary,
+    ) -> SamplingResult {
+        self.sampler(name, attributes).should_sample(
+            parent_context,
+            trace_id,
+            name,
+            span_kind,
+            attributes,
+            links,
+            instrumentation_library,
+        )
+    }
+}
+
+
+/// Match a gRPC method against a glob, `*` matches any run of characters (`/` included)
+/// and `?` a single one.
+///
+/// "grpc.health.v1.Health/*", "*/Watch", "shop.*/Get?"
+pub fn glob_match(glob: &str, method: &str) -> bool {
+    let (glob, method) = (glob.as_bytes(), method.as_bytes());
+    let (mut g, mut m) = (0, 0);
+    // glob & method positions right after the last `*`, to backtrack to
+    let mut star = None;
+    while m < method.len() {
+        match glob.get(g) {
+            Some(b'*') => {
+                star = Some((g + 1, m));
+                g += 1;
+            }
+            Some(&c) if c == b'?' || c == method[m] => {
+                g += 1;
+                m += 1;
+            }
+            _ => match star {
+                Some((star_g, star_m)) => {
+                    g = star_g;
+                    m = star_m + 1;
+                    star = Some((star_g, star_m + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[g..].iter().all(|&c| c == b'*')
+}
+
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::sdk::trace::{Sampler, ShouldSample};
+    use opentelemetry::trace::{OrderMap, SamplingDecision, SpanKind, TraceId};
+    use opentelemetry::{InstrumentationLibrary, Key, Value};
+
+    use super::{glob_match, MethodFilter, MethodSampler};
+
+    #[test]
+    fn glob() {
+        assert!(glob_match("grpc.health.v1.Health/*", "grpc.health.v1.Health/Check"));
+        assert!(glob_match("*/Watch", "grpc.health.v1.Health/Watch"));
+        assert!(glob_match("shop.*/Get?", "shop.Cart/GetA"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "aXbYbc"));
+        assert!(!glob_match("*/Watch", "grpc.health.v1.Health/Check"));
+        assert!(!glob_match("shop.*/Get?", "shop.Cart/Get"));
+        assert!(!glob_match("grpc.health.v1.Health", "grpc.health.v1.Health/Check"));
+    }
+
+    #[test]
+    fn filter() {
+        let filter = MethodFilter::exclude(["grpc.health.v1.Health/*", "grpc.reflection.*"]);
+        assert!(!filter.traces("/grpc.health.v1.Health/Check"));
+        assert!(!filter.traces("/grpc.reflection.v1alpha.ServerReflection/ServerReflectionInfo"));
+        assert!(filter.traces("/helloworld.Greeter/SayHello"));
+
+        let filter = MethodFilter::new(|method| method.starts_with("shop."));
+        assert!(filter.traces("/shop.Cart/Checkout"));
+        assert!(!filter.traces("/helloworld.Greeter/SayHello"));
+    }
+
+    #[test]
+    fn sampler() {
+        let sampler = MethodSampler::new(Sampler::AlwaysOff)
+            .with_rule("*/Watch", Sampler::AlwaysOff)
+            .with_rule("shop.Cart/*", Sampler::AlwaysOn);
+        let decision = |name: &str, attributes: &OrderMap<Key, Value>| {
+            sampler
+                .should_sample(
+                    None,
+                    TraceId::from_bytes([1; 16]),
+                    name,
+                    &SpanKind::Server,
+                    attributes,
+                    &[],
+                    &InstrumentationLibrary::new("test", None::<&str>, None::<&str>),
+                )
+                .decision
+        };
+
+        let no_attributes = OrderMap::default();
+        assert_eq!(decision("shop.Cart/Checkout", &no_attributes), SamplingDecision::RecordAndSample);
+        assert_eq!(decision("shop.Cart/Watch", &no_attributes), SamplingDecision::Drop);
+        assert_eq!(decision("other", &no_attributes), SamplingDecision::Drop);
+
+        let mut attributes = OrderMap::default();
+        attributes.insert(Key::new("rpc.service"), Value::from("shop.Cart"));
+        attributes.insert(Key::new("rpc.method"), Value::from("Checkout"));
+        assert_eq!(decision("checkout", &attributes), SamplingDecision::RecordAndSample);
+    }
+}
@@ -0,0 +1,184 @@
+//! OpenCensus binary trace context carried in the `grpc-trace-bin` metadata entry,
+//! as sent by gRPC's built-in OpenCensus tracing in Java and Go.
+//!
+//! Format, see <https://github.com/census-instrumentation/opencensus-specs/blob/master/encodings/BinaryEncoding.md>:
+//! ```text
+//! version(0) | 0 trace_id[16] | 1 span_id[8] | 2 trace_options[1]
+//! ```
+//!
+//! The layers & interceptors send and read it next to their propagator's entries with `with_grpc_trace_bin`.
+
+use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState};
+use opentelemetry::Context;
+
+use tonic::metadata::{MetadataMap, MetadataValue};
+
+
+pub const GRPC_TRACE_BIN_HEADER: &str = "grpc-trace-bin";
+
+const VERSION: u8 = 0;
+const TRACE_ID_FIELD: u8 = 0;
+const SPAN_ID_FIELD: u8 = 1;
+const TRACE_OPTIONS_FIELD: u8 = 2;
+const ENCODED_LEN: usize = 29;
+
+
+/// Propagator for the binary `grpc-trace-bin` entry, working on `MetadataMap` directly
+/// since it is binary metadata.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GrpcTraceBinPropagator {}
+
+impl GrpcTraceBinPropagator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn to_bytes(&self, span_context: &SpanContext) -> [u8; ENCODED_LEN] {
+        let mut bytes = [0; ENCODED_LEN];
+        bytes[0] = VERSION;
+        bytes[1] = TRACE_ID_FIELD;
+        bytes[2..18].copy_from_slice(&span_context.trace_id().to_bytes());
+        bytes[18] = SPAN_ID_FIELD;
+        bytes[19..27].copy_from_slice(&span_context.span_id().to_bytes());
+        bytes[27] = TRACE_OPTIONS_FIELD;
+        bytes[28] = span_context.trace_flags().to_u8();
+        bytes
+    }
+
+    /// Remote span context, `None` when malformed, invalid or of a version other than 0.
+    /// Trace options are optional, and bytes after the known fields are ignored.
+    pub fn from_bytes(&self, bytes: &[u8]) -> Option<SpanContext> {
+        let (&version, mut rest) = bytes.split_first()?;
+        if version != VERSION {
+            return None;
+        }
+
+        let trace_id = read_field(&mut rest, TRACE_ID_FIELD, 16)?;
+        let span_id = read_field(&mut rest, SPAN_ID_FIELD, 8)?;
+        let trace_flags = read_field(&mut rest, TRACE_OPTIONS_FIELD, 1)
+            .map(|options| TraceFlags::new(options[0] & TraceFlags::SAMPLED.to_u8()))
+            .unwrap_or_default();
+
+        let span_context = SpanContext::new(
+            TraceId::from_bytes(trace_id.try_into().ok()?),
+            SpanId::from_bytes(span_id.try_into().ok()?),
+            trace_flags,
+            true,
+            TraceState::default(),
+        );
+        span_context.is_valid().then_some(span_context)
+    }
+
+    /// Write the span context of `cx` into `grpc-trace-bin`, does nothing for an invalid span context.
+    pub fn inject_context(&self, cx: &Context, metadata: &mut MetadataMap) {
+        let span = cx.span();
+        let span_context = span.span_context();
+        if span_context.is_valid() {
+            metadata.insert_bin(
+                GRPC_TRACE_BIN_HEADER,
+                MetadataValue::from_bytes(&self.to_bytes(span_context)),
+            );
+        }
+    }
+
+    pub fn extract(&self, metadata: &MetadataMap) -> Context {
+        self.extract_with_context(&Context::current(), metadata)
+    }
+
+    /// `cx` with the remote span context from `grpc-trace-bin`, `cx` unchanged if missing or malformed.
+    pub fn extract_with_context(&self, cx: &Context, metadata: &MetadataMap) -> Context {
+        metadata
+            .get_bin(GRPC_TRACE_BIN_HEADER)
+            .and_then(|value| value.to_bytes().ok())
+            .and_then(|bytes| self.from_bytes(&bytes))
+            .map(|span_context| cx.with_remote_span_context(span_context))
+            .unwrap_or_else(|| cx.clone())
+    }
+}
+
+// `cx` extracted by a text propagator, or with the span context of `grpc-trace-bin` when it has none,
+// e.g. from OpenCensus clients sending only the binary entry
+pub(crate) fn or_grpc_trace_bin(cx: Context, metadata: &MetadataMap) -> Context {
+    match cx.span().span_context().is_valid() {
+        true => cx,
+        false => GrpcTraceBinPropagator::new().extract_with_context(&cx, metadata),
+    }
+}
+
+fn read_field<'a>(bytes: &mut &'a [u8], id: u8, len: usize) -> Option<&'a [u8]> {
+    if bytes.first() != Some(&id) || bytes.len() < len + 1 {
+        return None;
+    }
+    let (field, rest) = bytes[1..].split_at(len);
+    *bytes = rest;
+    Some(field)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState};
+    use opentelemetry::Context;
+    use tonic::metadata::MetadataMap;
+
+    use super::GrpcTraceBinPropagator;
+
+    fn span_context() -> SpanContext {
+        SpanContext::new(
+            TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap(),
+            SpanId::from_hex("00f067aa0ba902b7").unwrap(),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        )
+    }
+
+    #[test]
+    fn encode() {
+        let bytes = GrpcTraceBinPropagator::new().to_bytes(&span_context());
+        assert_eq!(
+            bytes.to_vec(),
+            [
+                vec![0, 0],
+                vec![0x4b, 0xf9, 0x2f, 0x35, 0x77, 0xb3, 0x4d, 0xa6, 0xa3, 0xce, 0x92, 0x9d, 0x0e, 0x0e, 0x47, 0x36],
+                vec![1],
+                vec![0x00, 0xf0, 0x67, 0xaa, 0x0b, 0xa9, 0x02, 0xb7],
+                vec![2, 1],
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn decode() {
+        let propagator = GrpcTraceBinPropagator::new();
+        let bytes = propagator.to_bytes(&span_context());
+
+        assert_eq!(propagator.from_bytes(&bytes), Some(span_context()));
+        // trace options are optional
+        let without_options = propagator.from_bytes(&bytes[..27]).unwrap();
+        assert_eq!(without_options.trace_flags(), TraceFlags::default());
+        // unknown trailing bytes
+        assert_eq!(propagator.from_bytes(&[&bytes[..], &[3, 42]].concat()), Some(span_context()));
+
+        assert_eq!(propagator.from_bytes(&[]), None);
+        assert_eq!(propagator.from_bytes(&bytes[..20]), None);
+        let mut unknown_version = bytes;
+        unknown_version[0] = 1;
+        assert_eq!(propagator.from_bytes(&unknown_version), None);
+        assert_eq!(propagator.from_bytes(&[0; 29]), None);
+    }
+
+    #[test]
+    fn metadata_round_trip() {
+        let propagator = GrpcTraceBinPropagator::new();
+        let mut metadata = MetadataMap::new();
+        propagator.inject_context(&Context::new().with_remote_span_context(span_context()), &mut metadata);
+        assert!(metadata.get_bin("grpc-trace-bin").is_some());
+
+        let cx = propagator.extract(&metadata);
+        assert_eq!(cx.span().span_context(), &span_context());
+
+        assert!(!propagator.extract(&MetadataMap::new()).has_active_span());
+    }
+}
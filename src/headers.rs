@@ -0,0 +1,167 @@
+use std::str::FromStr;
+
+use opentelemetry::propagation::{Extractor, Injector, TextMapPropagator};
+use opentelemetry::Context;
+
+use http::header::{HeaderName, HeaderValue};
+use http::HeaderMap;
+
+use tonic::metadata::MetadataMap;
+
+use crate::encoding::{EncodingInjector, Encodings};
+use crate::report::InjectionReport;
+
+
+/// `Injector` over plain `http::HeaderMap`, for hyper/axum services next to tonic ones.
+///
+/// Same behaviour as `MetadataInjector`: invalid keys or values are ignored, and so are
+/// `-bin` keys which gRPC reserves for base64 binary values.
+pub struct HeaderInjector<'a>(pub &'a mut HeaderMap);
+
+impl<'a> Injector for HeaderInjector<'a> {
+    /// Set a key and value in the HeaderMap.  Does nothing if the key or value are not valid inputs
+    fn set(&mut self, key: &str, value: String) {
+        if is_binary(key) {
+            return;
+        }
+        if let Ok(key) = HeaderName::from_str(key) {
+            if let Ok(val) = HeaderValue::from_str(&value) {
+                self.0.insert(key, val);
+            }
+        }
+    }
+}
+
+
+/// `Extractor` over plain `http::HeaderMap`, the counterpart of [`HeaderInjector`].
+///
+/// Same behaviour as `MetadataExtractor`: `-bin` keys are listed but have no value.
+pub struct HeaderExtractor<'a>(pub &'a HeaderMap);
+
+impl<'a> Extractor for HeaderExtractor<'a> {
+    /// Get a value for a key from the HeaderMap.  If the value can't be converted to &str, returns None
+    fn get(&self, key: &str) -> Option<&str> {
+        if is_binary(key) {
+            return None;
+        }
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    /// Collect all the keys from the HeaderMap.
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(HeaderName::as_str).collect()
+    }
+}
+
+// inject into plain http headers with the checks & report of `EncodingInjector`,
+// like `CheckedMetadataInjector` with the default encodings
+pub(crate) fn inject_headers(
+    propagator: &dyn TextMapPropagator,
+    cx: &Context,
+    headers: &mut HeaderMap,
+    encodings: &Encodings,
+) -> InjectionReport {
+    with_metadata(headers, |metadata| {
+        let mut injector = EncodingInjector::new(metadata, encodings);
+        propagator.inject_context(cx, &mut injector);
+        injector.into_report()
+    })
+}
+
+// `f` over the headers as gRPC metadata, both are the same map
+pub(crate) fn with_metadata<T>(headers: &mut HeaderMap, f: impl FnOnce(&mut MetadataMap) -> T) -> T {
+    let mut metadata = MetadataMap::from_headers(std::mem::take(headers));
+    let result = f(&mut metadata);
+    *headers = metadata.into_headers();
+    result
+}
+
+// `-bin` suffix in any case, compared as bytes since the key may not be ascii
+pub(crate) fn is_binary(key: &str) -> bool {
+    key.len() >= 4 && key.as_bytes()[key.len() - 4..].eq_ignore_ascii_case(b"-bin")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::propagation::{text_map_propagator::FieldIter, Extractor, Injector, TextMapPropagator};
+    use opentelemetry::Context;
+    use tonic::metadata::MetadataMap;
+
+    use super::{inject_headers, HeaderExtractor, HeaderInjector};
+    use crate::{Encodings, InjectionError, MetadataExtractor, MetadataInjector};
+
+    #[test]
+    fn same_as_metadata() {
+        let entries = [
+            ("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+            ("Baggage", "tenant.id=acme"),
+            ("grpc-trace-bin", "AAA"),
+            ("bad key", "value"),
+            ("tracestate", "bad\nvalue"),
+            ("a€bc", "value"),
+        ];
+
+        let mut headers = http::HeaderMap::new();
+        let mut metadata = MetadataMap::new();
+        for (key, value) in entries {
+            HeaderInjector(&mut headers).set(key, value.to_string());
+            MetadataInjector(&mut metadata).set(key, value.to_string());
+        }
+        assert_eq!(headers, metadata.clone().into_headers());
+        assert_eq!(headers.len(), 2);
+
+        headers.insert("grpc-trace-bin", "AAA".parse().unwrap());
+        let metadata = MetadataMap::from_headers(headers.clone());
+        let (header_extractor, metadata_extractor) = (HeaderExtractor(&headers), MetadataExtractor(&metadata));
+        for key in ["traceparent", "baggage", "grpc-trace-bin", "missing", "a€bc", "x€-BIN"] {
+            assert_eq!(header_extractor.get(key), metadata_extractor.get(key));
+        }
+        let mut keys = header_extractor.keys();
+        keys.sort_unstable();
+        assert_eq!(keys, ["baggage", "grpc-trace-bin", "traceparent"]);
+    }
+
+    // sends its entries as is
+    #[derive(Debug)]
+    struct Entries(Vec<(&'static str, &'static str)>);
+
+    impl TextMapPropagator for Entries {
+        fn inject_context(&self, _cx: &Context, injector: &mut dyn Injector) {
+            for (key, value) in &self.0 {
+                injector.set(key, value.to_string());
+            }
+        }
+
+        fn extract_with_context(&self, cx: &Context, _extractor: &dyn Extractor) -> Context {
+            cx.clone()
+        }
+
+        fn fields(&self) -> FieldIter<'_> {
+            FieldIter::new(&[])
+        }
+    }
+
+    #[test]
+    fn checked_injection() {
+        let propagator = Entries(vec![
+            ("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+            ("grpc-trace-bin", "AAA"),
+            ("tracestate", "bad\nvalue"),
+        ]);
+        let mut headers = http::HeaderMap::new();
+        headers.insert("grpc-timeout", "1S".parse().unwrap());
+
+        let report = inject_headers(&propagator, &Context::new(), &mut headers, &Encodings::default());
+        assert_eq!(
+            report.errors,
+            [
+                InjectionError::InvalidKey { key: "grpc-trace-bin".to_string() },
+                InjectionError::InvalidValue { key: "tracestate".to_string(), value: "bad\nvalue".to_string() },
+            ]
+        );
+        let mut keys: Vec<_> = headers.keys().map(|key| key.as_str()).collect();
+        keys.sort_unstable();
+        assert_eq!(keys, ["grpc-timeout", "traceparent"]);
+    }
+}
@@ -0,0 +1,307 @@
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::Context;
+
+use tonic::metadata::MetadataMap;
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+use crate::grpc_trace_bin::{or_grpc_trace_bin, GrpcTraceBinPropagator, GRPC_TRACE_BIN_HEADER};
+use crate::limits::ExtractLimits;
+use crate::propagator::Propagator;
+use crate::{ContextSource, EncodingExtractor, EncodingInjector, Encodings};
+
+
+/// Client `Interceptor` injecting the current context, for use with `ServiceClient::with_interceptor`.
+///
+/// pre-requisite, unless [`with_propagator`](Self::with_propagator) is used:
+/// global::set_text_map_propagator(TraceContextPropagator::new());
+#[derive(Debug, Clone, Default)]
+pub struct TraceInjectInterceptor {
+    source: ContextSource,
+    propagator: Propagator,
+    encodings: Encodings,
+    grpc_trace_bin: bool,
+}
+
+impl TraceInjectInterceptor {
+    /// Inject the context of the current `tracing::Span`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inject the current thread-bound OTel `Context` instead of the tracing span.
+    pub fn from_otel_context() -> Self {
+        Self::with_source(ContextSource::OtelContext)
+    }
+
+    pub fn with_source(source: ContextSource) -> Self {
+        TraceInjectInterceptor {
+            source,
+            ..Self::default()
+        }
+    }
+
+    /// Inject with this propagator instead of the global one.
+    pub fn with_propagator<P>(mut self, propagator: P) -> Self
+    where
+        P: TextMapPropagator + Send + Sync + 'static,
+    {
+        self.propagator = Propagator::new(propagator);
+        self
+    }
+
+    /// Encode the values of these keys, e.g. percent-encoded baggage, instead of sending them as is.
+    /// The server needs the same `Encodings`, see [`TraceExtractInterceptor::with_encodings`].
+    pub fn with_encodings(mut self, encodings: Encodings) -> Self {
+        self.encodings = encodings;
+        self
+    }
+
+    /// Also send the span context in the binary `grpc-trace-bin` entry,
+    /// for servers using gRPC's OpenCensus tracing, see [`grpc_trace_bin`](crate::grpc_trace_bin).
+    pub fn with_grpc_trace_bin(mut self) -> Self {
+        self.grpc_trace_bin = true;
+        self
+    }
+}
+
+impl Interceptor for TraceInjectInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let cx = self.source.current();
+        // rejected entries are logged with tracing::warn!
+        self.propagator.with(|propagator| {
+            propagator.inject_context(&cx, &mut EncodingInjector::new(request.metadata_mut(), &self.encodings))
+        });
+        if self.grpc_trace_bin {
+            GrpcTraceBinPropagator::new().inject_context(&cx, request.metadata_mut());
+        }
+        Ok(request)
+    }
+}
+
+
+/// Server `Interceptor` extracting the remote context into the request extensions,
+/// for use with `ServiceServer::with_interceptor`. Read it back with [`extracted_context`].
+///
+/// pre-requisite, unless [`with_propagator`](Self::with_propagator) is used:
+/// global::set_text_map_propagator(TraceContextPropagator::new());
+#[derive(Debug, Clone, Default)]
+pub struct TraceExtractInterceptor {
+    propagator: Propagator,
+    encodings: Encodings,
+    limits: Option<ExtractLimits>,
+    grpc_trace_bin: bool,
+    // headers the limits apply to, read from the propagator on the first call
+    limited_fields: Option<Vec<String>>,
+}
+
+impl TraceExtractInterceptor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Extract with this propagator instead of the global one.
+    pub fn with_propagator<P>(mut self, propagator: P) -> Self
+    where
+        P: TextMapPropagator + Send + Sync + 'static,
+    {
+        self.propagator = Propagator::new(propagator);
+        self.limited_fields = None;
+        self
+    }
+
+    /// Decode the values of these keys, as encoded by [`TraceInjectInterceptor::with_encodings`].
+    pub fn with_encodings(mut self, encodings: Encodings) -> Self {
+        self.encodings = encodings;
+        self
+    }
+
+    /// Limit the size of the propagator's headers before extracting them,
+    /// a request rejected by the limits fails with `INVALID_ARGUMENT`.
+    pub fn with_limits(mut self, limits: ExtractLimits) -> Self {
+        self.limits = Some(limits);
+        self
+    }
+
+    /// Also read the span context from the binary `grpc-trace-bin` entry when the propagator finds none,
+    /// e.g. from clients using gRPC's OpenCensus tracing, see [`grpc_trace_bin`](crate::grpc_trace_bin).
+    pub fn with_grpc_trace_bin(mut self) -> Self {
+        self.grpc_trace_bin = true;
+        self.limited_fields = None;
+        self
+    }
+}
+
+impl Interceptor for TraceExtractInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(limits) = &self.limits {
+            let (propagator, grpc_trace_bin) = (&self.propagator, self.grpc_trace_bin);
+            let fields = self.limited_fields.get_or_insert_with(|| {
+                let mut fields: Vec<String> = propagator.with(|propagator| propagator.fields().map(str::to_string).collect());
+                if grpc_trace_bin {
+                    fields.push(GRPC_TRACE_BIN_HEADER.to_string());
+                }
+                fields
+            });
+            let mut headers = std::mem::take(request.metadata_mut()).into_headers();
+            limits.apply(&mut headers, fields.iter().map(String::as_str))?;
+            *request.metadata_mut() = MetadataMap::from_headers(headers);
+        }
+        let mut cx = self.propagator.with(|propagator| {
+            propagator.extract(&EncodingExtractor::new(request.metadata(), &self.encodings))
+        });
+        if self.grpc_trace_bin {
+            cx = or_grpc_trace_bin(cx, request.metadata());
+        }
+        request.extensions_mut().insert(ExtractedContext(cx));
+        Ok(request)
+    }
+}
+
+
+#[derive(Clone)]
+struct ExtractedContext(Context);
+
+/// Context stored by [`TraceExtractInterceptor`], `None` if the interceptor is not installed.
+///
+/// e.g. tracing::Span::current().set_parent(extracted_context(&request).unwrap_or_default());
+pub fn extracted_context<T>(request: &Request<T>) -> Option<Context> {
+    request
+        .extensions()
+        .get::<ExtractedContext>()
+        .map(|extracted| extracted.0.clone())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::baggage::BaggageExt;
+    use opentelemetry::{global, Context, KeyValue, Value};
+    use opentelemetry::sdk::propagation::{BaggagePropagator, TraceContextPropagator};
+    use opentelemetry::sdk::trace::TracerProvider;
+    use opentelemetry::trace::{Span, Tracer, TracerProvider as _, TraceContextExt};
+    use tonic::service::Interceptor;
+    use tonic::{Code, Request};
+
+    use super::{extracted_context, TraceExtractInterceptor, TraceInjectInterceptor};
+    use crate::testing::TenantPropagator;
+    use crate::{Encoding, Encodings, ExtractLimits, LimitAction};
+
+    #[test]
+    fn round_trip() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let provider = TracerProvider::builder().build();
+        let span = provider.tracer("test").start("client-span");
+        let trace_id = span.span_context().trace_id();
+        let _cx = Context::current_with_span(span).attach();
+
+        let request = TraceInjectInterceptor::from_otel_context()
+            .call(Request::new(()))
+            .unwrap();
+        let request = TraceExtractInterceptor::new().call(request).unwrap();
+
+        let cx = extracted_context(&request).unwrap();
+        assert_eq!(cx.span().span_context().trace_id(), trace_id);
+        assert!(cx.span().span_context().is_remote());
+    }
+
+    #[test]
+    fn explicit_propagator() {
+        let _cx = Context::current_with_baggage(vec![KeyValue::new("tenant.id", "acme")]).attach();
+
+        let request = TraceInjectInterceptor::from_otel_context()
+            .with_propagator(BaggagePropagator::new())
+            .call(Request::new(()))
+            .unwrap();
+        assert!(request.metadata().get("traceparent").is_none());
+        drop(_cx);
+
+        let request = TraceExtractInterceptor::new()
+            .with_propagator(BaggagePropagator::new())
+            .call(request)
+            .unwrap();
+        let cx = extracted_context(&request).unwrap();
+        assert_eq!(cx.baggage().get("tenant.id"), Some(&Value::from("acme")));
+    }
+
+    #[test]
+    fn encodings() {
+        let propagator = TenantPropagator::new;
+        let encodings = Encodings::new().with_key("x-tenant", Encoding::Percent);
+        let _cx = Context::current_with_baggage(vec![KeyValue::new("tenant", "Zürich\nNY")]).attach();
+
+        let request = TraceInjectInterceptor::from_otel_context()
+            .with_propagator(propagator())
+            .call(Request::new(()))
+            .unwrap();
+        assert!(request.metadata().get("x-tenant").is_none());
+
+        let request = TraceInjectInterceptor::from_otel_context()
+            .with_propagator(propagator())
+            .with_encodings(encodings.clone())
+            .call(Request::new(()))
+            .unwrap();
+        drop(_cx);
+        let request = TraceExtractInterceptor::new()
+            .with_propagator(propagator())
+            .with_encodings(encodings)
+            .call(request)
+            .unwrap();
+        let cx = extracted_context(&request).unwrap();
+        assert_eq!(cx.baggage().get("tenant"), Some(&Value::from("Zürich\nNY")));
+    }
+
+    #[test]
+    fn limits() {
+        let mut request = Request::new(());
+        request.metadata_mut().insert("baggage", "a=1,b=2,c=3".parse().unwrap());
+        let mut interceptor = TraceExtractInterceptor::new()
+            .with_propagator(BaggagePropagator::new())
+            .with_limits(ExtractLimits::new().with_max_value_len(8).with_action(LimitAction::Truncate));
+        let request = interceptor.call(request).unwrap();
+        assert_eq!(request.metadata().get("baggage").unwrap(), "a=1,b=2");
+        let cx = extracted_context(&request).unwrap();
+        assert_eq!(cx.baggage().len(), 2);
+
+        let status = TraceExtractInterceptor::new()
+            .with_propagator(BaggagePropagator::new())
+            .with_limits(ExtractLimits::new().with_max_value_len(4).with_action(LimitAction::Reject))
+            .call(request)
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+
+    #[test]
+    fn grpc_trace_bin() {
+        let provider = TracerProvider::builder().build();
+        let span = provider.tracer("test").start("client-span");
+        let span_context = span.span_context().clone();
+        let _cx = Context::current_with_span(span).attach();
+
+        // only the binary entry carries the span context
+        let request = TraceInjectInterceptor::from_otel_context()
+            .with_propagator(BaggagePropagator::new())
+            .with_grpc_trace_bin()
+            .call(Request::new(()))
+            .unwrap();
+        assert!(request.metadata().get_bin("grpc-trace-bin").is_some());
+        assert!(request.metadata().get("traceparent").is_none());
+        drop(_cx);
+
+        let metadata = request.metadata().clone();
+        let extract = |interceptor: TraceExtractInterceptor| {
+            let request = Request::from_parts(metadata.clone(), Default::default(), ());
+            let request = interceptor.with_propagator(BaggagePropagator::new()).call(request).unwrap();
+            extracted_context(&request).unwrap().span().span_context().clone()
+        };
+        let extracted = extract(TraceExtractInterceptor::new().with_grpc_trace_bin());
+        assert_eq!(extracted.trace_id(), span_context.trace_id());
+        assert_eq!(extracted.span_id(), span_context.span_id());
+        assert!(!extract(TraceExtractInterceptor::new()).is_valid());
+    }
+
+    #[test]
+    fn missing_interceptor() {
+        assert!(extracted_context(&Request::new(())).is_none());
+    }
+}
@@ -1,7 +1,9 @@
+use std::future::Future;
 use std::str::FromStr;
 
 use opentelemetry::{global, Context, ContextGuard};
-use opentelemetry::propagation::{Extractor, Injector};
+use opentelemetry::propagation::{Extractor, Injector, TextMapPropagator};
+use opentelemetry::trace::{FutureExt, WithContext};
 
 use tonic::metadata::{MetadataKey, KeyRef, MetadataMap};
 use tonic::Request;
@@ -9,6 +11,69 @@ use tonic::Request;
 // extend tracing::Span with context()
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 
+use crate::headers::inject_headers;
+
+pub mod baggage;
+mod binary;
+#[cfg(feature = "build")]
+pub mod build;
+mod client;
+#[cfg(feature = "fmt")]
+mod correlation;
+mod encoding;
+mod filter;
+pub mod grpc_trace_bin;
+mod headers;
+mod interceptor;
+mod limits;
+pub mod message;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod propagator;
+mod report;
+mod response;
+pub mod semconv;
+mod server;
+mod span_ext;
+mod stream;
+mod trace_id;
+mod traced_rpc;
+mod trust;
+// span recorder of the tests, shared with build-tests, not part of the API
+#[doc(hidden)]
+pub mod testing;
+
+pub use binary::{BinaryMetadataExtractor, BinaryMetadataInjector};
+pub use client::{ContextSource, TraceInjectLayer, TraceInjectService};
+#[cfg(feature = "fmt")]
+pub use correlation::{trace_ids, WithTraceIds};
+pub use encoding::{Encoding, EncodingExtractor, EncodingInjector, Encodings};
+pub use filter::{glob_match, MethodFilter, MethodSampler};
+pub use headers::{HeaderExtractor, HeaderInjector};
+pub use interceptor::{extracted_context, TraceExtractInterceptor, TraceInjectInterceptor};
+pub use limits::{ExtractLimits, LimitAction, LimitViolation};
+#[cfg(feature = "metrics")]
+pub use metrics::{MeteredBody, MeteredRequestBody, MetricsFuture, MetricsLayer, MetricsService};
+pub use report::{CheckedMetadataInjector, InjectionError, InjectionReport};
+pub use response::{ResponseFuture, TracedBody};
+pub use server::{TraceExtractLayer, TraceExtractService};
+pub use stream::TracedStream;
+pub use trace_id::{trace_id_from_metadata, trace_id_from_response, trace_id_from_status, TRACE_ID_HEADER};
+pub use trust::{peer_addr, TrustPolicy};
+#[cfg(feature = "macros")]
+pub use opentelemetry_tonic_macros::traced_rpc;
+
+// used by the code generated by #[traced_rpc] and build::generate
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::traced_rpc::{
+        client_span, inject, inject_with, server_span, server_span_with, traced, traced_client, TracedRpc,
+    };
+}
+
+// lets the crate's own tests use #[traced_rpc], which refers to ::opentelemetry_tonic
+#[cfg(test)]
+extern crate self as opentelemetry_tonic;
 
 pub struct MetadataInjector<'a>(&'a mut MetadataMap);
 
@@ -26,6 +91,17 @@ impl<'a> Injector for MetadataInjector<'a> {
 
 pub struct MetadataExtractor<'a>(&'a MetadataMap);
 
+impl<'a> MetadataExtractor<'a> {
+    pub fn new(metadata: &'a MetadataMap) -> Self {
+        MetadataExtractor(metadata)
+    }
+
+    /// Decode the values of these keys, as encoded by an [`EncodingInjector`] with the same `Encodings`.
+    pub fn with_encodings(self, encodings: &'a Encodings) -> EncodingExtractor<'a> {
+        EncodingExtractor::new(self.0, encodings)
+    }
+}
+
 impl<'a> Extractor for MetadataExtractor<'a> {
     /// Get a value for a key from the MetadataMap.  If the value can't be converted to &str, returns None
     fn get(&self, key: &str) -> Option<&str> {
@@ -48,20 +124,63 @@ impl<'a> Extractor for MetadataExtractor<'a> {
 // pre-requisite:
 // global::set_text_map_propagator(TraceContextPropagator::new());
 pub fn tracing_parent_span_from_req<T>(request: &Request<T>){
-		let cx = global::get_text_map_propagator(|propagator| {
-				propagator.extract(&MetadataExtractor(request.metadata()))
-		});
+		global::get_text_map_propagator(|propagator| tracing_parent_span_from_req_with(request, propagator))
+}
+
+// same as tracing_parent_span_from_req, with an explicit propagator instead of the global one
+pub fn tracing_parent_span_from_req_with<T>(request: &Request<T>, propagator: &dyn TextMapPropagator) {
+		let cx = propagator.extract(&MetadataExtractor(request.metadata()));
 
 		tracing::Span::current().set_parent(cx);
 }
 
+// same as tracing_parent_span_from_req_with, decoding the values encoded per encodings
+// e.g. by TraceInjectInterceptor::with_encodings
+pub fn tracing_parent_span_from_req_with_encodings<T>(request: &Request<T>, propagator: &dyn TextMapPropagator, encodings: &Encodings) {
+		let cx = propagator.extract(&EncodingExtractor::new(request.metadata(), encodings));
+
+		tracing::Span::current().set_parent(cx);
+}
+
+// pre-requisite:
+// global::set_text_map_propagator(TraceContextPropagator::new());
+// same as tracing_parent_span_from_req, applying policy to the context sent by the client
+// e.g. TrustPolicy::LinkOnly on public endpoints
+pub fn tracing_parent_span_from_req_with_policy<T>(request: &Request<T>, policy: TrustPolicy){
+		global::get_text_map_propagator(|propagator| tracing_parent_span_from_req_with_propagator_and_policy(request, propagator, policy))
+}
+
+// same as tracing_parent_span_from_req_with_policy, with an explicit propagator instead of the global one
+pub fn tracing_parent_span_from_req_with_propagator_and_policy<T>(request: &Request<T>, propagator: &dyn TextMapPropagator, policy: TrustPolicy) {
+		let cx = propagator.extract(&MetadataExtractor(request.metadata()));
+
+		policy.apply(&tracing::Span::current(), cx);
+}
+
 // pre-requisite:
 // global::set_text_map_propagator(TraceContextPropagator::new());
+// entries which could not be sent are logged with tracing::warn!, see tracing_current_span_to_req_checked
 pub fn tracing_current_span_to_req<T>(request: &mut Request<T>){
+		let _ = tracing_current_span_to_req_checked(request);
+}
+
+// same as tracing_current_span_to_req, returning the entries which could not be sent
+pub fn tracing_current_span_to_req_checked<T>(request: &mut Request<T>) -> InjectionReport {
+		global::get_text_map_propagator(|propagator| tracing_current_span_to_req_with(request, propagator))
+}
+
+// same as tracing_current_span_to_req, with an explicit propagator instead of the global one
+pub fn tracing_current_span_to_req_with<T>(request: &mut Request<T>, propagator: &dyn TextMapPropagator) -> InjectionReport {
+		tracing_current_span_to_req_with_encodings(request, propagator, &Encodings::default())
+}
+
+// same as tracing_current_span_to_req_with, encoding the values per encodings
+// the server needs the same encodings, e.g. tracing_parent_span_from_req_with_encodings
+pub fn tracing_current_span_to_req_with_encodings<T>(request: &mut Request<T>, propagator: &dyn TextMapPropagator, encodings: &Encodings) -> InjectionReport {
 		let cx = tracing::Span::current().context();
-		global::get_text_map_propagator(|propagator| {
-				propagator.inject_context(&cx, &mut MetadataInjector(request.metadata_mut()))
-		});
+		let mut injector = EncodingInjector::new(request.metadata_mut(), encodings);
+		propagator.inject_context(&cx, &mut injector);
+		injector.into_report()
 }
 
 // pre-requisite:
@@ -69,36 +188,140 @@ pub fn tracing_current_span_to_req<T>(request: &mut Request<T>){
 // context is bind to thread, not like tracing::Span
 // need to hold returned guard(e.g. let _xx = ) for this new context to take effect
 // context will restore when guard dropped
+// in async handlers use with_remote_context, the guard does not follow the task across await points
 pub fn otel_thread_cx_from_req<T>(request: &Request<T>)  -> ContextGuard {
+		global::get_text_map_propagator(|propagator| otel_thread_cx_from_req_with(request, propagator))
+}
+
+// same as otel_thread_cx_from_req, with an explicit propagator instead of the global one
+pub fn otel_thread_cx_from_req_with<T>(request: &Request<T>, propagator: &dyn TextMapPropagator)  -> ContextGuard {
+		let cx = propagator.extract(&MetadataExtractor(request.metadata()));
+		cx.attach()
+}
+
+// same as otel_thread_cx_from_req_with, decoding the values encoded per encodings
+pub fn otel_thread_cx_from_req_with_encodings<T>(request: &Request<T>, propagator: &dyn TextMapPropagator, encodings: &Encodings)  -> ContextGuard {
+		let cx = propagator.extract(&EncodingExtractor::new(request.metadata(), encodings));
+		cx.attach()
+}
+
+// pre-requisite:
+// global::set_text_map_propagator(TraceContextPropagator::new());
+// context is attached only while fut is polled, so it follows the handler across await points & threads
+// e.g. with_remote_context(&request, async { ... }).await
+pub fn with_remote_context<T, F: Future>(request: &Request<T>, fut: F) -> WithContext<F> {
 		let cx = global::get_text_map_propagator(|propagator| {
 				propagator.extract(&MetadataExtractor(request.metadata()))
 		});
-		cx.attach()
+		fut.with_context(cx)
+}
+
+// same as with_remote_context, with an explicit propagator instead of the global one
+pub fn with_remote_context_with<T, F: Future>(request: &Request<T>, propagator: &dyn TextMapPropagator, fut: F) -> WithContext<F> {
+		let cx = propagator.extract(&MetadataExtractor(request.metadata()));
+		fut.with_context(cx)
 }
 
 // pre-requisite:
 // global::set_text_map_propagator(TraceContextPropagator::new());
+// entries which could not be sent are logged with tracing::warn!, see otel_thread_cx_to_req_checked
 pub fn otel_thread_cx_to_req<T>(request: &mut Request<T>){
+		let _ = otel_thread_cx_to_req_checked(request);
+}
+
+// same as otel_thread_cx_to_req, returning the entries which could not be sent
+pub fn otel_thread_cx_to_req_checked<T>(request: &mut Request<T>) -> InjectionReport {
+		global::get_text_map_propagator(|propagator| otel_thread_cx_to_req_with(request, propagator))
+}
+
+// same as otel_thread_cx_to_req, with an explicit propagator instead of the global one
+pub fn otel_thread_cx_to_req_with<T>(request: &mut Request<T>, propagator: &dyn TextMapPropagator) -> InjectionReport {
+		otel_thread_cx_to_req_with_encodings(request, propagator, &Encodings::default())
+}
+
+// same as otel_thread_cx_to_req_with, encoding the values per encodings
+pub fn otel_thread_cx_to_req_with_encodings<T>(request: &mut Request<T>, propagator: &dyn TextMapPropagator, encodings: &Encodings) -> InjectionReport {
 		let cx = Context::current();
-		global::get_text_map_propagator(|propagator| {
-				propagator.inject_context(&cx, &mut MetadataInjector(request.metadata_mut()))
-		});
+		let mut injector = EncodingInjector::new(request.metadata_mut(), encodings);
+		propagator.inject_context(&cx, &mut injector);
+		injector.into_report()
+}
+
+// pre-requisite:
+// global::set_text_map_propagator(TraceContextPropagator::new());
+// same as tracing_parent_span_from_req, for plain http (e.g. hyper/axum) requests
+pub fn tracing_parent_span_from_http_req<B>(request: &http::Request<B>){
+		global::get_text_map_propagator(|propagator| tracing_parent_span_from_http_req_with(request, propagator))
+}
+
+// same as tracing_parent_span_from_http_req, with an explicit propagator instead of the global one
+pub fn tracing_parent_span_from_http_req_with<B>(request: &http::Request<B>, propagator: &dyn TextMapPropagator) {
+		let cx = propagator.extract(&HeaderExtractor(request.headers()));
+
+		tracing::Span::current().set_parent(cx);
+}
+
+// pre-requisite:
+// global::set_text_map_propagator(TraceContextPropagator::new());
+// same as tracing_current_span_to_req, for plain http (e.g. hyper/reqwest) requests
+// returns the entries which could not be sent, each also logged with tracing::warn!
+pub fn tracing_current_span_to_http_req<B>(request: &mut http::Request<B>) -> InjectionReport {
+		global::get_text_map_propagator(|propagator| tracing_current_span_to_http_req_with(request, propagator))
+}
+
+// same as tracing_current_span_to_http_req, with an explicit propagator instead of the global one
+pub fn tracing_current_span_to_http_req_with<B>(request: &mut http::Request<B>, propagator: &dyn TextMapPropagator) -> InjectionReport {
+		let cx = tracing::Span::current().context();
+		inject_headers(propagator, &cx, request.headers_mut(), &Encodings::default())
+}
+
+// pre-requisite:
+// global::set_text_map_propagator(TraceContextPropagator::new());
+// e.g. to return the server context to the caller in http response headers
+// returns the entries which could not be sent, each also logged with tracing::warn!
+pub fn tracing_current_span_to_http_res<B>(response: &mut http::Response<B>) -> InjectionReport {
+		global::get_text_map_propagator(|propagator| tracing_current_span_to_http_res_with(response, propagator))
+}
+
+// same as tracing_current_span_to_http_res, with an explicit propagator instead of the global one
+pub fn tracing_current_span_to_http_res_with<B>(response: &mut http::Response<B>, propagator: &dyn TextMapPropagator) -> InjectionReport {
+		let cx = tracing::Span::current().context();
+		inject_headers(propagator, &cx, response.headers_mut(), &Encodings::default())
 }
 
 
 #[cfg(test)]
 mod tests {
-		use opentelemetry::{global, Context};
+		use opentelemetry::baggage::BaggageExt;
+		use opentelemetry::propagation::TextMapPropagator;
+		use opentelemetry::{global, Context, KeyValue, Value};
 		use opentelemetry::sdk::{
 				propagation::TraceContextPropagator,
 				export::trace::stdout
 		};
-		use opentelemetry::trace::{Tracer, TraceContextExt};
+		use opentelemetry::sdk::trace::TracerProvider;
+		use opentelemetry::trace::{Span, Tracer, TracerProvider as _, TraceContextExt};
+		use tracing_opentelemetry::OpenTelemetrySpanExt;
 
 		use super::MetadataExtractor;
 
 		use super::MetadataInjector;
 
+		use super::{otel_thread_cx_to_req_with, with_remote_context, with_remote_context_with};
+
+		use super::{otel_thread_cx_from_req_with_encodings, otel_thread_cx_to_req_with_encodings, Encoding, Encodings};
+
+		use super::{tracing_current_span_to_http_req, tracing_parent_span_from_http_req};
+
+		use super::{
+				tracing_current_span_to_http_req_with, tracing_current_span_to_http_res_with,
+				tracing_parent_span_from_http_req_with, tracing_parent_span_from_req_with_propagator_and_policy,
+		};
+
+		use crate::TrustPolicy;
+
+		use crate::testing::{SpanRecorder, TenantPropagator};
+
     #[test]
     fn inject() {
 				global::set_text_map_propagator(TraceContextPropagator::new());
@@ -131,4 +354,114 @@ mod tests {
 				let _span = tracer.start_with_context("server-span", &cx);
 
     }
+
+		#[tokio::test]
+		async fn remote_context_across_await() {
+				global::set_text_map_propagator(TraceContextPropagator::new());
+				let provider = TracerProvider::builder().build();
+				let span = provider.tracer("test").start("client-span");
+				let trace_id = span.span_context().trace_id();
+				let cx = Context::current_with_span(span);
+
+				let mut request = tonic::Request::new(1);
+				global::get_text_map_propagator(|propagator| {
+						propagator.inject_context(&cx, &mut MetadataInjector(request.metadata_mut()))
+				});
+
+				let handler = with_remote_context(&request, async {
+						tokio::task::yield_now().await;
+						Context::current().span().span_context().trace_id()
+				});
+				assert_eq!(handler.await, trace_id);
+				assert!(!Context::current().has_active_span());
+		}
+
+		#[tokio::test]
+		async fn explicit_propagator() {
+				let propagator = TraceContextPropagator::new();
+				let provider = TracerProvider::builder().build();
+				let span = provider.tracer("test").start("client-span");
+				let trace_id = span.span_context().trace_id();
+
+				let mut request = tonic::Request::new(1);
+				{
+						let _cx = Context::current_with_span(span).attach();
+						assert!(otel_thread_cx_to_req_with(&mut request, &propagator).is_ok());
+				}
+				assert!(request.metadata().get("traceparent").is_some());
+
+				let handler = with_remote_context_with(&request, &propagator, async {
+						Context::current().span().span_context().trace_id()
+				});
+				assert_eq!(handler.await, trace_id);
+		}
+
+		#[test]
+		fn encodings() {
+				let propagator = TenantPropagator::new();
+				let encodings = Encodings::new().with_key("x-tenant", Encoding::Percent);
+				let mut request = tonic::Request::new(1);
+				{
+						let _cx = Context::current_with_baggage(vec![KeyValue::new("tenant", "Zürich NY")]).attach();
+						assert!(otel_thread_cx_to_req_with_encodings(&mut request, &propagator, &encodings).is_ok());
+				}
+				assert_eq!(request.metadata().get("x-tenant").unwrap(), "Z%C3%BCrich%20NY");
+
+				let extractor = MetadataExtractor::new(request.metadata()).with_encodings(&encodings);
+				assert_eq!(propagator.extract(&extractor).baggage().get("tenant"), Some(&Value::from("Zürich NY")));
+				let _cx = otel_thread_cx_from_req_with_encodings(&request, &propagator, &encodings);
+				assert_eq!(Context::current().baggage().get("tenant"), Some(&Value::from("Zürich NY")));
+		}
+
+		#[test]
+		fn http_round_trip() {
+				global::set_text_map_propagator(TraceContextPropagator::new());
+				let recorder = SpanRecorder::default();
+				let (_provider, subscriber) = recorder.subscriber();
+				let _guard = tracing::subscriber::set_default(subscriber);
+
+				let mut request = http::Request::new(());
+				let client = tracing::info_span!("client");
+				assert!(client.in_scope(|| tracing_current_span_to_http_req(&mut request)).is_ok());
+				assert!(request.headers().contains_key("traceparent"));
+
+				let server = tracing::info_span!("server");
+				server.in_scope(|| tracing_parent_span_from_http_req(&request));
+				assert_eq!(
+						server.context().span().span_context().trace_id(),
+						client.context().span().span_context().trace_id()
+				);
+		}
+
+		#[test]
+		fn explicit_propagator_helpers() {
+				let propagator = TraceContextPropagator::new();
+				let recorder = SpanRecorder::default();
+				let (_provider, subscriber) = recorder.subscriber();
+				let _guard = tracing::subscriber::set_default(subscriber);
+				let trace_id = |span: &tracing::Span| span.context().span().span_context().trace_id();
+
+				let mut request = http::Request::new(());
+				let client = tracing::info_span!("client");
+				assert!(client.in_scope(|| tracing_current_span_to_http_req_with(&mut request, &propagator)).is_ok());
+
+				let server = tracing::info_span!("server");
+				server.in_scope(|| tracing_parent_span_from_http_req_with(&request, &propagator));
+				assert_eq!(trace_id(&server), trace_id(&client));
+
+				let mut response = http::Response::new(());
+				assert!(server.in_scope(|| tracing_current_span_to_http_res_with(&mut response, &propagator)).is_ok());
+				assert!(response.headers()["traceparent"].to_str().unwrap().contains(&trace_id(&client).to_string()));
+
+				let request = tonic::Request::from_parts(
+						tonic::metadata::MetadataMap::from_headers(request.headers().clone()),
+						Default::default(),
+						(),
+				);
+				let ignoring = tracing::info_span!("ignoring");
+				ignoring.in_scope(|| {
+						tracing_parent_span_from_req_with_propagator_and_policy(&request, &propagator, TrustPolicy::Ignore)
+				});
+				assert_ne!(trace_id(&ignoring), trace_id(&client));
+		}
 }
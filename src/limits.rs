@@ -0,0 +1,319 @@
+use std::fmt;
+
+use http::header::{HeaderName, HeaderValue};
+use http::HeaderMap;
+#[cfg(feature = "metrics")]
+use opentelemetry::metrics::{Counter, Meter};
+#[cfg(feature = "metrics")]
+use opentelemetry::{Context, KeyValue};
+use tonic::Status;
+
+
+/// What to do with a trace context header over an [`ExtractLimits`] limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LimitAction {
+    /// Keep the leading list members which fit, e.g. of `baggage` or `tracestate`,
+    /// the header is removed when none does.
+    Truncate,
+    /// Remove the header, as if the client had not sent it.
+    #[default]
+    Drop,
+    /// Fail the RPC with `INVALID_ARGUMENT`.
+    Reject,
+}
+
+impl LimitAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LimitAction::Truncate => "truncate",
+            LimitAction::Drop => "drop",
+            LimitAction::Reject => "reject",
+        }
+    }
+}
+
+
+/// A limit of [`ExtractLimits`] a request went over.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LimitViolation {
+    ValueLength { header: String },
+    BaggageMembers,
+    TotalBytes { header: String },
+}
+
+impl LimitViolation {
+    /// Name of the limit, the `limit` attribute of the violation metric.
+    pub fn limit(&self) -> &'static str {
+        match self {
+            LimitViolation::ValueLength { .. } => "value_length",
+            LimitViolation::BaggageMembers => "baggage_members",
+            LimitViolation::TotalBytes { .. } => "total_bytes",
+        }
+    }
+
+    fn header(&self) -> &str {
+        match self {
+            LimitViolation::ValueLength { header } | LimitViolation::TotalBytes { header } => header,
+            LimitViolation::BaggageMembers => BAGGAGE_HEADER,
+        }
+    }
+}
+
+impl fmt::Display for LimitViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} over the {} limit", self.header(), self.limit())
+    }
+}
+
+impl From<LimitViolation> for Status {
+    fn from(violation: LimitViolation) -> Self {
+        Status::invalid_argument(format!("trace context {}", violation))
+    }
+}
+
+
+const BAGGAGE_HEADER: &str = "baggage";
+
+/// Limits on the trace context headers sent by the client, applied before the propagators parse them,
+/// e.g. against huge `tracestate` or `baggage` headers from untrusted callers.
+///
+/// Values of a header sent several times are counted joined with `,`.
+///
+/// ```ignore
+/// TraceExtractLayer::new().with_limits(
+///     ExtractLimits::new()
+///         .with_max_value_len(512)
+///         .with_max_baggage_members(16)
+///         .with_action(LimitAction::Truncate),
+/// )
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ExtractLimits {
+    max_value_len: Option<usize>,
+    max_baggage_members: Option<usize>,
+    max_total_bytes: Option<usize>,
+    action: LimitAction,
+    #[cfg(feature = "metrics")]
+    violations: Option<Counter<u64>>,
+}
+
+impl ExtractLimits {
+    /// No limit, see the `with_` methods.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Max bytes of the value of a header.
+    pub fn with_max_value_len(mut self, max: usize) -> Self {
+        self.max_value_len = Some(max);
+        self
+    }
+
+    /// Max members of the `baggage` header.
+    pub fn with_max_baggage_members(mut self, max: usize) -> Self {
+        self.max_baggage_members = Some(max);
+        self
+    }
+
+    /// Max bytes of the values of all the headers.
+    pub fn with_max_total_bytes(mut self, max: usize) -> Self {
+        self.max_total_bytes = Some(max);
+        self
+    }
+
+    /// [`LimitAction::Drop`] by default.
+    pub fn with_action(mut self, action: LimitAction) -> Self {
+        self.action = action;
+        self
+    }
+
+    /// Count violations in `rpc.server.trace_context.limit_violations`, by `limit` & `action`.
+    #[cfg(feature = "metrics")]
+    pub fn with_meter(mut self, meter: &Meter) -> Self {
+        self.violations = Some(
+            meter
+                .u64_counter("rpc.server.trace_context.limit_violations")
+                .with_description("Counts trace context headers over the extraction limits.")
+                .init(),
+        );
+        self
+    }
+
+    /// Apply the limits to the `fields` headers, in order, and return the violations.
+    /// Err with the first violation for [`LimitAction::Reject`], into `INVALID_ARGUMENT` with `Status::from`.
+    pub fn apply<'a>(
+        &self,
+        headers: &mut HeaderMap,
+        fields: impl IntoIterator<Item = &'a str>,
+    ) -> Result<Vec<LimitViolation>, LimitViolation> {
+        let mut violations = Vec::new();
+        let mut total = 0;
+        for field in fields {
+            let Ok(name) = HeaderName::from_bytes(field.as_bytes()) else { continue };
+            let values: Vec<&str> = headers.get_all(&name).iter().filter_map(|value| value.to_str().ok()).collect();
+            if values.is_empty() {
+                continue;
+            }
+            let original = values.join(",");
+            let mut value = original.clone();
+
+            if name == BAGGAGE_HEADER {
+                if let Some(max) = self.max_baggage_members {
+                    if value.split(',').count() > max {
+                        self.violation(&mut violations, LimitViolation::BaggageMembers)?;
+                        value = self.fit(&value, |members| members.len() <= max, usize::MAX);
+                    }
+                }
+            }
+            if let Some(max) = self.max_value_len {
+                if value.len() > max {
+                    self.violation(&mut violations, LimitViolation::ValueLength { header: field.to_string() })?;
+                    value = self.fit(&value, |_| true, max);
+                }
+            }
+            if let Some(max) = self.max_total_bytes {
+                if total + value.len() > max {
+                    self.violation(&mut violations, LimitViolation::TotalBytes { header: field.to_string() })?;
+                    value = self.fit(&value, |_| true, max - total);
+                }
+            }
+            total += value.len();
+
+            if value != original {
+                headers.remove(&name);
+                if let Ok(value) = HeaderValue::from_str(&value) {
+                    if !value.is_empty() {
+                        headers.insert(name, value);
+                    }
+                }
+            }
+        }
+        Ok(violations)
+    }
+
+    fn violation(&self, violations: &mut Vec<LimitViolation>, violation: LimitViolation) -> Result<(), LimitViolation> {
+        tracing::warn!(
+            header = violation.header(),
+            limit = violation.limit(),
+            action = self.action.as_str(),
+            "trace context over limit"
+        );
+        #[cfg(feature = "metrics")]
+        if let Some(counter) = &self.violations {
+            counter.add(
+                &Context::current(),
+                1,
+                &[
+                    KeyValue::new("limit", violation.limit()),
+                    KeyValue::new("action", self.action.as_str()),
+                ],
+            );
+        }
+        if self.action == LimitAction::Reject {
+            return Err(violation);
+        }
+        violations.push(violation);
+        Ok(())
+    }
+
+    // value once the action is applied: the leading members which fit for Truncate, nothing for Drop
+    fn fit(&self, value: &str, members_fit: impl Fn(&[&str]) -> bool, max_len: usize) -> String {
+        if self.action != LimitAction::Truncate {
+            return String::new();
+        }
+        let mut members: Vec<&str> = Vec::new();
+        for member in value.split(',') {
+            members.push(member);
+            if !members_fit(&members) || members.join(",").len() > max_len {
+                members.pop();
+                break;
+            }
+        }
+        members.join(",")
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use http::HeaderMap;
+    use tonic::{Code, Status};
+
+    use super::{ExtractLimits, LimitAction, LimitViolation};
+
+    const FIELDS: [&str; 3] = ["traceparent", "tracestate", "baggage"];
+
+    fn headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".parse().unwrap());
+        headers.insert("tracestate", "a=1,b=2,c=3".parse().unwrap());
+        headers.append("baggage", "k1=v1,k2=v2".parse().unwrap());
+        headers.append("baggage", "k3=v3".parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn truncate() {
+        let limits = ExtractLimits::new()
+            .with_max_value_len(8)
+            .with_max_baggage_members(2)
+            .with_action(LimitAction::Truncate);
+        let mut headers = headers();
+        let violations = limits.apply(&mut headers, FIELDS).unwrap();
+        assert_eq!(
+            violations,
+            [
+                LimitViolation::ValueLength { header: "traceparent".to_string() },
+                LimitViolation::ValueLength { header: "tracestate".to_string() },
+                LimitViolation::BaggageMembers,
+                LimitViolation::ValueLength { header: "baggage".to_string() },
+            ]
+        );
+        assert!(headers.get("traceparent").is_none());
+        assert_eq!(headers.get("tracestate").unwrap(), "a=1,b=2");
+        assert_eq!(headers.get_all("baggage").iter().collect::<Vec<_>>(), ["k1=v1"]);
+    }
+
+    #[test]
+    fn total_bytes() {
+        let mut headers = headers();
+        let violations = ExtractLimits::new()
+            .with_max_total_bytes(60)
+            .with_action(LimitAction::Truncate)
+            .apply(&mut headers, FIELDS)
+            .unwrap();
+        assert_eq!(
+            violations,
+            [
+                LimitViolation::TotalBytes { header: "tracestate".to_string() },
+                LimitViolation::TotalBytes { header: "baggage".to_string() },
+            ]
+        );
+        assert!(headers.contains_key("traceparent"));
+        assert_eq!(headers.get("tracestate").unwrap(), "a=1");
+        assert!(headers.get("baggage").is_none());
+
+        // within the limits
+        let mut headers = self::headers();
+        assert_eq!(ExtractLimits::new().with_max_total_bytes(100).apply(&mut headers, FIELDS).unwrap(), []);
+        assert_eq!(headers, self::headers());
+    }
+
+    #[test]
+    fn drop_and_reject() {
+        let mut headers = headers();
+        let limits = ExtractLimits::new().with_max_baggage_members(2);
+        assert_eq!(limits.apply(&mut headers, FIELDS).unwrap(), [LimitViolation::BaggageMembers]);
+        assert!(!headers.contains_key("baggage"));
+        assert_eq!(headers.get("tracestate").unwrap(), "a=1,b=2,c=3");
+
+        let violation = limits
+            .with_action(LimitAction::Reject)
+            .apply(&mut self::headers(), FIELDS)
+            .unwrap_err();
+        assert_eq!(violation, LimitViolation::BaggageMembers);
+        let status = Status::from(violation);
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(status.message(), "trace context baggage over the baggage_members limit");
+    }
+}
@@ -0,0 +1,131 @@
+//! Trace context carried inside stream messages, for long-lived streams whose messages are
+//! independent units of work.
+//!
+//! Give the message a `map<string, string>` field and implement [`ContextCarrier`] over it:
+//!
+//! ```ignore
+//! // message WorkItem { map<string, string> trace_context = 15; ... }
+//! impl ContextCarrier for WorkItem {
+//!     fn carrier(&self) -> &HashMap<String, String> {
+//!         &self.trace_context
+//!     }
+//!
+//!     fn carrier_mut(&mut self) -> &mut HashMap<String, String> {
+//!         &mut self.trace_context
+//!     }
+//! }
+//! ```
+
+use std::collections::HashMap;
+
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::{global, Context};
+
+// extend tracing::Span with context() & set_parent()
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+
+/// Message with a string map field holding its trace context.
+pub trait ContextCarrier {
+    fn carrier(&self) -> &HashMap<String, String>;
+
+    fn carrier_mut(&mut self) -> &mut HashMap<String, String>;
+}
+
+impl ContextCarrier for HashMap<String, String> {
+    fn carrier(&self) -> &HashMap<String, String> {
+        self
+    }
+
+    fn carrier_mut(&mut self) -> &mut HashMap<String, String> {
+        self
+    }
+}
+
+
+/// Write `cx` into the message with the global propagator, like `MetadataInjector` does for metadata.
+pub fn cx_to_msg<M: ContextCarrier>(message: &mut M, cx: &Context) {
+    global::get_text_map_propagator(|propagator| cx_to_msg_with(message, cx, propagator))
+}
+
+/// Same as [`cx_to_msg`], with an explicit propagator instead of the global one.
+pub fn cx_to_msg_with<M: ContextCarrier>(message: &mut M, cx: &Context, propagator: &dyn TextMapPropagator) {
+    propagator.inject_context(cx, message.carrier_mut())
+}
+
+/// Context sent with the message, with the global propagator.
+pub fn cx_from_msg<M: ContextCarrier>(message: &M) -> Context {
+    global::get_text_map_propagator(|propagator| cx_from_msg_with(message, propagator))
+}
+
+/// Same as [`cx_from_msg`], with an explicit propagator instead of the global one.
+pub fn cx_from_msg_with<M: ContextCarrier>(message: &M, propagator: &dyn TextMapPropagator) -> Context {
+    propagator.extract(message.carrier())
+}
+
+/// Send the context of the current `tracing::Span` with the message.
+pub fn tracing_current_span_to_msg<M: ContextCarrier>(message: &mut M) {
+    cx_to_msg(message, &tracing::Span::current().context())
+}
+
+/// Parent the current `tracing::Span` on the context sent with the message,
+/// e.g. in a span created per received message.
+pub fn tracing_parent_span_from_msg<M: ContextCarrier>(message: &M) {
+    tracing::Span::current().set_parent(cx_from_msg(message))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use opentelemetry::sdk::propagation::TraceContextPropagator;
+    use opentelemetry::trace::TraceContextExt;
+    use opentelemetry::{global, Context};
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    use super::{cx_from_msg, tracing_current_span_to_msg, tracing_parent_span_from_msg, ContextCarrier};
+    use crate::testing::SpanRecorder;
+
+    #[derive(Default)]
+    struct WorkItem {
+        trace_context: HashMap<String, String>,
+    }
+
+    impl ContextCarrier for WorkItem {
+        fn carrier(&self) -> &HashMap<String, String> {
+            &self.trace_context
+        }
+
+        fn carrier_mut(&mut self) -> &mut HashMap<String, String> {
+            &mut self.trace_context
+        }
+    }
+
+    #[test]
+    fn per_message_context() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let recorder = SpanRecorder::default();
+        let (_provider, subscriber) = recorder.subscriber();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        // two items of the same stream, sent from different traces
+        let (first, second) = (tracing::info_span!("first"), tracing::info_span!("second"));
+        let mut items = [WorkItem::default(), WorkItem::default()];
+        first.in_scope(|| tracing_current_span_to_msg(&mut items[0]));
+        second.in_scope(|| tracing_current_span_to_msg(&mut items[1]));
+        assert!(items[0].trace_context.contains_key("traceparent"));
+
+        for (item, sender) in items.iter().zip([&first, &second]) {
+            let handler = tracing::info_span!("handle");
+            handler.in_scope(|| tracing_parent_span_from_msg(item));
+            assert_eq!(
+                handler.context().span().span_context().trace_id(),
+                sender.context().span().span_context().trace_id()
+            );
+        }
+
+        assert!(!cx_from_msg(&HashMap::new()).has_active_span());
+        assert!(!Context::current().has_active_span());
+    }
+}
@@ -0,0 +1,495 @@
+//! RPC metrics, see <https://opentelemetry.io/docs/specs/semconv/rpc/rpc-metrics/>
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context as TaskContext, Poll};
+use std::time::Instant;
+
+use futures_core::Stream;
+use http_body::{Body, SizeHint};
+use opentelemetry::metrics::{Histogram, Meter, Unit};
+use opentelemetry::{Context, KeyValue};
+use pin_project::pin_project;
+
+use tonic::body::BoxBody;
+use tonic::{Code, Status};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::semconv::{rpc_attributes, RPC_GRPC_STATUS_CODE};
+
+
+struct Instruments {
+    duration: Histogram<f64>,
+    request_size: Histogram<u64>,
+    response_size: Histogram<u64>,
+    requests_per_rpc: Histogram<u64>,
+    responses_per_rpc: Histogram<u64>,
+}
+
+impl Instruments {
+    // kind is "server" or "client"
+    fn new(meter: &Meter, kind: &str) -> Self {
+        Instruments {
+            duration: meter
+                .f64_histogram(format!("rpc.{}.duration", kind))
+                .with_description("Measures the duration of inbound/outbound RPC.")
+                .with_unit(Unit::new("ms"))
+                .init(),
+            request_size: meter
+                .u64_histogram(format!("rpc.{}.request.size", kind))
+                .with_description("Measures the size of RPC request messages.")
+                .with_unit(Unit::new("By"))
+                .init(),
+            response_size: meter
+                .u64_histogram(format!("rpc.{}.response.size", kind))
+                .with_description("Measures the size of RPC response messages.")
+                .with_unit(Unit::new("By"))
+                .init(),
+            requests_per_rpc: meter
+                .u64_histogram(format!("rpc.{}.requests_per_rpc", kind))
+                .with_description("Measures the number of messages received per RPC.")
+                .with_unit(Unit::new("{count}"))
+                .init(),
+            responses_per_rpc: meter
+                .u64_histogram(format!("rpc.{}.responses_per_rpc", kind))
+                .with_description("Measures the number of messages sent per RPC.")
+                .with_unit(Unit::new("{count}"))
+                .init(),
+        }
+    }
+}
+
+
+/// `Layer` recording `rpc.server.*` or `rpc.client.*` duration, message size and
+/// messages per RPC histograms.
+///
+/// Sizes are the uncompressed message payloads, as in the semantic conventions: a message sent
+/// compressed (`grpc-encoding`) is counted in `*_per_rpc`, but its size is not recorded.
+///
+/// ```ignore
+/// Server::builder()
+///     .layer(MetricsLayer::server(&global::meter("my-service")))
+///     .layer(TraceExtractLayer::new())
+///     ...
+/// ```
+#[derive(Clone)]
+pub struct MetricsLayer {
+    instruments: Arc<Instruments>,
+}
+
+impl MetricsLayer {
+    pub fn server(meter: &Meter) -> Self {
+        MetricsLayer {
+            instruments: Arc::new(Instruments::new(meter, "server")),
+        }
+    }
+
+    pub fn client(meter: &Meter) -> Self {
+        MetricsLayer {
+            instruments: Arc::new(Instruments::new(meter, "client")),
+        }
+    }
+}
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = MetricsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        MetricsService {
+            inner,
+            instruments: self.instruments.clone(),
+        }
+    }
+}
+
+
+/// Service created by [`MetricsLayer`].
+#[derive(Clone)]
+pub struct MetricsService<S> {
+    inner: S,
+    instruments: Arc<Instruments>,
+}
+
+impl<S, B, ResBody> Service<http::Request<B>> for MetricsService<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<ResBody>>,
+    B: MeteredRequestBody,
+{
+    type Response = http::Response<MeteredBody<ResBody>>;
+    type Error = S::Error;
+    type Future = MetricsFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let rpc = Arc::new(RpcMetrics::new(self.instruments.clone(), request.uri().path()));
+        let request = request
+            .map(|body| B::from_metered(MeteredBody::new(body, rpc.clone(), Direction::Request)));
+
+        MetricsFuture {
+            inner: self.inner.call(request),
+            rpc: Some(rpc),
+        }
+    }
+}
+
+
+/// Request bodies [`MetricsService`] can count messages of: tonic's client `BoxBody`
+/// and server `transport::Body`.
+pub trait MeteredRequestBody: Body + Sized {
+    fn from_metered(body: MeteredBody<Self>) -> Self;
+}
+
+impl MeteredRequestBody for BoxBody {
+    fn from_metered(body: MeteredBody<Self>) -> Self {
+        body.boxed_unsync()
+    }
+}
+
+impl MeteredRequestBody for tonic::transport::Body {
+    fn from_metered(body: MeteredBody<Self>) -> Self {
+        tonic::transport::Body::wrap_stream(body)
+    }
+}
+
+
+/// Response future of [`MetricsService`].
+#[pin_project]
+pub struct MetricsFuture<F> {
+    #[pin]
+    inner: F,
+    rpc: Option<Arc<RpcMetrics>>,
+}
+
+impl<F, B, E> Future for MetricsFuture<F>
+where
+    F: Future<Output = Result<http::Response<B>, E>>,
+{
+    type Output = Result<http::Response<MeteredBody<B>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let response = match this.inner.poll(cx) {
+            Poll::Ready(Ok(response)) => response,
+            Poll::Ready(Err(err)) => {
+                this.rpc.take();
+                return Poll::Ready(Err(err));
+            }
+            Poll::Pending => return Poll::Pending,
+        };
+
+        let rpc = this.rpc.take().expect("polled after completion");
+        // trailers-only response
+        if let Some(status) = Status::from_header_map(response.headers()) {
+            rpc.set_status(status.code());
+        }
+        Poll::Ready(Ok(response.map(|body| MeteredBody::new(body, rpc, Direction::Response))))
+    }
+}
+
+
+#[derive(Debug, Clone, Copy)]
+enum Direction {
+    Request,
+    Response,
+}
+
+/// Body counting the gRPC messages going through it.
+#[pin_project]
+pub struct MeteredBody<B> {
+    #[pin]
+    inner: B,
+    rpc: Arc<RpcMetrics>,
+    direction: Direction,
+}
+
+impl<B> MeteredBody<B> {
+    fn new(inner: B, rpc: Arc<RpcMetrics>, direction: Direction) -> Self {
+        MeteredBody { inner, rpc, direction }
+    }
+}
+
+impl<B> Body for MeteredBody<B>
+where
+    B: Body,
+    B::Data: AsRef<[u8]>,
+{
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_data(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let this = self.project();
+        let data = this.inner.poll_data(cx);
+        if let Poll::Ready(Some(Ok(data))) = &data {
+            this.rpc.count(*this.direction, data.as_ref());
+        }
+        data
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+    ) -> Poll<Result<Option<http::HeaderMap>, Self::Error>> {
+        let this = self.project();
+        let trailers = this.inner.poll_trailers(cx);
+        if let Poll::Ready(Ok(Some(trailers))) = &trailers {
+            if let Some(status) = Status::from_header_map(trailers) {
+                this.rpc.set_status(status.code());
+            }
+        }
+        trailers
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+// lets hyper::Body::wrap_stream take the server request body, request trailers are not used by gRPC
+impl<B> Stream for MeteredBody<B>
+where
+    B: Body,
+    B::Data: AsRef<[u8]>,
+{
+    type Item = Result<B::Data, B::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        self.poll_data(cx)
+    }
+}
+
+
+// gRPC length-prefixed message framing: 1 byte compressed flag, 4 bytes big-endian length, payload
+#[derive(Debug, Default)]
+struct MessageCounter {
+    header: [u8; 5],
+    header_len: usize,
+    remaining: usize,
+    messages: u64,
+}
+
+impl MessageCounter {
+    // calls on_message with the payload length of every uncompressed message starting in data
+    fn update(&mut self, mut data: &[u8], mut on_message: impl FnMut(u64)) {
+        while !data.is_empty() {
+            if self.remaining > 0 {
+                let n = self.remaining.min(data.len());
+                self.remaining -= n;
+                data = &data[n..];
+                continue;
+            }
+
+            let n = (self.header.len() - self.header_len).min(data.len());
+            self.header[self.header_len..self.header_len + n].copy_from_slice(&data[..n]);
+            self.header_len += n;
+            data = &data[n..];
+
+            if self.header_len == self.header.len() {
+                let len = u32::from_be_bytes([self.header[1], self.header[2], self.header[3], self.header[4]]);
+                self.messages += 1;
+                self.header_len = 0;
+                self.remaining = len as usize;
+                if self.header[0] == 0 {
+                    on_message(len as u64);
+                }
+            }
+        }
+    }
+}
+
+
+// shared by the request and response bodies, records duration and messages per RPC once both are dropped
+struct RpcMetrics {
+    instruments: Arc<Instruments>,
+    attributes: Vec<KeyValue>,
+    start: Instant,
+    request: Mutex<MessageCounter>,
+    response: Mutex<MessageCounter>,
+    status: Mutex<Option<Code>>,
+}
+
+impl RpcMetrics {
+    fn new(instruments: Arc<Instruments>, path: &str) -> Self {
+        RpcMetrics {
+            instruments,
+            attributes: rpc_attributes(path),
+            start: Instant::now(),
+            request: Mutex::default(),
+            response: Mutex::default(),
+            status: Mutex::default(),
+        }
+    }
+
+    fn count(&self, direction: Direction, data: &[u8]) {
+        let cx = Context::current();
+        let (counter, size) = match direction {
+            Direction::Request => (&self.request, &self.instruments.request_size),
+            Direction::Response => (&self.response, &self.instruments.response_size),
+        };
+        counter
+            .lock()
+            .unwrap()
+            .update(data, |len| size.record(&cx, len, &self.attributes));
+    }
+
+    fn set_status(&self, code: Code) {
+        self.status.lock().unwrap().get_or_insert(code);
+    }
+}
+
+impl Drop for RpcMetrics {
+    fn drop(&mut self) {
+        let cx = Context::current();
+        let mut attributes = self.attributes.clone();
+        if let Some(code) = *self.status.get_mut().unwrap() {
+            attributes.push(KeyValue::new(RPC_GRPC_STATUS_CODE, code as i64));
+        }
+
+        let duration = self.start.elapsed().as_secs_f64() * 1000.0;
+        self.instruments.duration.record(&cx, duration, &attributes);
+        self.instruments
+            .requests_per_rpc
+            .record(&cx, self.request.get_mut().unwrap().messages, &attributes);
+        self.instruments
+            .responses_per_rpc
+            .record(&cx, self.response.get_mut().unwrap().messages, &attributes);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::sync::{Arc, Mutex};
+
+    use http_body::Body as _;
+    use opentelemetry::metrics::{Histogram, InstrumentProvider, Meter, Result, SyncHistogram, Unit};
+    use opentelemetry::{Context, InstrumentationLibrary, KeyValue, Value};
+    use tonic::transport::Body;
+    use tower::{service_fn, ServiceBuilder, ServiceExt};
+
+    use super::{MessageCounter, MetricsLayer};
+
+    type Records = Arc<Mutex<Vec<(String, f64, Vec<KeyValue>)>>>;
+
+    // instrument provider keeping every recorded value
+    #[derive(Default)]
+    struct Recorder(Records);
+
+    struct RecordingHistogram(String, Records);
+
+    impl SyncHistogram<u64> for RecordingHistogram {
+        fn record(&self, _cx: &Context, value: u64, attributes: &[KeyValue]) {
+            self.1.lock().unwrap().push((self.0.clone(), value as f64, attributes.to_vec()));
+        }
+    }
+
+    impl SyncHistogram<f64> for RecordingHistogram {
+        fn record(&self, _cx: &Context, value: f64, attributes: &[KeyValue]) {
+            self.1.lock().unwrap().push((self.0.clone(), value, attributes.to_vec()));
+        }
+    }
+
+    impl InstrumentProvider for Recorder {
+        fn f64_histogram(&self, name: String, _: Option<String>, _: Option<Unit>) -> Result<Histogram<f64>> {
+            Ok(Histogram::new(Arc::new(RecordingHistogram(name, self.0.clone()))))
+        }
+
+        fn u64_histogram(&self, name: String, _: Option<String>, _: Option<Unit>) -> Result<Histogram<u64>> {
+            Ok(Histogram::new(Arc::new(RecordingHistogram(name, self.0.clone()))))
+        }
+
+        fn register_callback(&self, _callback: Box<dyn Fn(&Context) + Send + Sync>) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn message(payload: &[u8]) -> Vec<u8> {
+        frame(0, payload)
+    }
+
+    fn frame(compressed: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![compressed];
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn count_split_messages() {
+        let mut data = message(b"hello");
+        data.extend(message(b""));
+        data.extend(frame(1, b"gzipped"));
+        data.extend(message(b"world!"));
+
+        let mut counter = MessageCounter::default();
+        let mut sizes = Vec::new();
+        for chunk in data.chunks(3) {
+            counter.update(chunk, |len| sizes.push(len));
+        }
+        // the compressed message is counted, without its size
+        assert_eq!(counter.messages, 4);
+        assert_eq!(sizes, vec![5, 0, 6]);
+    }
+
+    #[tokio::test]
+    async fn server_metrics() {
+        let records = Records::default();
+        let meter = Meter::new(
+            InstrumentationLibrary::new("test", None, None),
+            Arc::new(Recorder(records.clone())),
+        );
+
+        let request = http::Request::builder()
+            .uri("/helloworld.Greeter/SayHello")
+            .body(Body::from(message(b"request")))
+            .unwrap();
+        let mut response = ServiceBuilder::new()
+            .layer(MetricsLayer::server(&meter))
+            .service(service_fn(|mut request: http::Request<Body>| async move {
+                while request.body_mut().data().await.is_some() {}
+                let mut response = http::Response::new(Body::from(message(b"hi")));
+                response.headers_mut().insert("grpc-status", "0".parse().unwrap());
+                Ok::<_, Infallible>(response)
+            }))
+            .oneshot(request)
+            .await
+            .unwrap();
+        while response.body_mut().data().await.is_some() {}
+        drop(response);
+
+        let records = records.lock().unwrap();
+        let value = |name: &str| {
+            records
+                .iter()
+                .find(|(record, _, _)| record == name)
+                .map(|(_, value, _)| *value)
+                .unwrap_or_else(|| panic!("{} not recorded", name))
+        };
+        assert_eq!(value("rpc.server.request.size"), 7.0);
+        assert_eq!(value("rpc.server.response.size"), 2.0);
+        assert_eq!(value("rpc.server.requests_per_rpc"), 1.0);
+        assert_eq!(value("rpc.server.responses_per_rpc"), 1.0);
+        assert!(value("rpc.server.duration") >= 0.0);
+
+        let (_, _, attributes) = records
+            .iter()
+            .find(|(name, _, _)| name == "rpc.server.duration")
+            .unwrap();
+        assert!(attributes.contains(&KeyValue::new("rpc.method", "SayHello")));
+        assert!(attributes
+            .iter()
+            .any(|kv| kv.key.as_str() == "rpc.grpc.status_code" && kv.value == Value::I64(0)));
+    }
+}
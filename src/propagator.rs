@@ -0,0 +1,25 @@
+use std::sync::Arc;
+
+use opentelemetry::global;
+use opentelemetry::propagation::TextMapPropagator;
+
+
+// propagator of the layers & interceptors, the global one unless set with `with_propagator`
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Propagator(Option<Arc<dyn TextMapPropagator + Send + Sync>>);
+
+impl Propagator {
+    pub(crate) fn new<P>(propagator: P) -> Self
+    where
+        P: TextMapPropagator + Send + Sync + 'static,
+    {
+        Propagator(Some(Arc::new(propagator)))
+    }
+
+    pub(crate) fn with<T>(&self, mut f: impl FnMut(&dyn TextMapPropagator) -> T) -> T {
+        match &self.0 {
+            Some(propagator) => f(propagator.as_ref()),
+            None => global::get_text_map_propagator(f),
+        }
+    }
+}
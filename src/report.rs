@@ -0,0 +1,124 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use opentelemetry::propagation::Injector;
+
+use tonic::metadata::{Ascii, MetadataKey, MetadataMap, MetadataValue};
+
+
+/// An entry a propagator wrote which the metadata can't carry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InjectionError {
+    /// Not a valid ascii metadata key, e.g. with spaces or a `-bin` suffix.
+    InvalidKey { key: String },
+    /// Not a valid metadata value, e.g. with a line break or another control character.
+    InvalidValue { key: String, value: String },
+}
+
+impl InjectionError {
+    pub fn key(&self) -> &str {
+        match self {
+            InjectionError::InvalidKey { key } | InjectionError::InvalidValue { key, .. } => key,
+        }
+    }
+}
+
+impl fmt::Display for InjectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InjectionError::InvalidKey { key } => write!(f, "invalid metadata key {:?}", key),
+            InjectionError::InvalidValue { key, value } => write!(f, "invalid metadata value {:?} for {:?}", value, key),
+        }
+    }
+}
+
+impl Error for InjectionError {}
+
+
+/// Entries dropped while injecting a context, empty when everything was sent.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[must_use = "entries the metadata rejected are only logged unless the report is checked"]
+pub struct InjectionReport {
+    pub errors: Vec<InjectionError>,
+}
+
+impl InjectionReport {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+
+/// `Injector` like `MetadataInjector`, which records the entries it can't insert
+/// and emits a `tracing::warn!` for each, instead of dropping them silently.
+pub struct CheckedMetadataInjector<'a> {
+    metadata: &'a mut MetadataMap,
+    report: InjectionReport,
+}
+
+impl<'a> CheckedMetadataInjector<'a> {
+    pub fn new(metadata: &'a mut MetadataMap) -> Self {
+        CheckedMetadataInjector {
+            metadata,
+            report: InjectionReport::default(),
+        }
+    }
+
+    pub fn into_report(self) -> InjectionReport {
+        self.report
+    }
+}
+
+impl<'a> Injector for CheckedMetadataInjector<'a> {
+    /// Set a key and value in the MetadataMap.  Records an error if the key or value are not valid inputs
+    fn set(&mut self, key: &str, value: String) {
+        let Ok(metadata_key) = MetadataKey::from_str(key) else {
+            return reject(&mut self.report, InjectionError::InvalidKey { key: key.to_string() });
+        };
+        match value.parse::<MetadataValue<Ascii>>() {
+            Ok(val) => {
+                self.metadata.insert(metadata_key, val);
+            }
+            Err(_) => reject(&mut self.report, InjectionError::InvalidValue { key: key.to_string(), value }),
+        }
+    }
+}
+
+// log & record an entry an injector could not insert
+pub(crate) fn reject(report: &mut InjectionReport, error: InjectionError) {
+    tracing::warn!(key = error.key(), error = %error, "trace context entry not injected");
+    report.errors.push(error);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::propagation::Injector;
+    use tonic::metadata::MetadataMap;
+
+    use super::{CheckedMetadataInjector, InjectionError};
+
+    #[test]
+    fn rejected_entries() {
+        let mut metadata = MetadataMap::new();
+        let mut injector = CheckedMetadataInjector::new(&mut metadata);
+        injector.set("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".to_string());
+        injector.set("bad key", "value".to_string());
+        injector.set("grpc-trace-bin", "AAA".to_string());
+        injector.set("baggage", "city=Zürich\nNY".to_string());
+
+        let report = injector.into_report();
+        assert!(!report.is_ok());
+        assert_eq!(
+            report.errors,
+            [
+                InjectionError::InvalidKey { key: "bad key".to_string() },
+                InjectionError::InvalidKey { key: "grpc-trace-bin".to_string() },
+                InjectionError::InvalidValue { key: "baggage".to_string(), value: "city=Zürich\nNY".to_string() },
+            ]
+        );
+        assert_eq!(report.errors[2].to_string(), "invalid metadata value \"city=Zürich\\nNY\" for \"baggage\"");
+        assert_eq!(metadata.len(), 1);
+    }
+}
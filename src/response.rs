@@ -0,0 +1,258 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context as TaskContext, Poll};
+use std::time::Instant;
+
+use http_body::{Body, SizeHint};
+use pin_project::{pin_project, pinned_drop};
+use tonic::{Code, Status};
+
+use crate::semconv::{grpc_timeout, record_cancelled, record_status, RpcKind};
+
+
+/// Response future of the client and server layers, keeps the RPC span open until
+/// the response body (and its trailers) are consumed.
+///
+/// On the server, dropping it or the body before the status is sent (the client cancelled the call)
+/// records `CANCELLED` and a `cancelled` event.
+#[pin_project(PinnedDrop)]
+pub struct ResponseFuture<F> {
+    // None when the layer answers itself with `rejected`
+    #[pin]
+    inner: Option<F>,
+    rejected: Option<Status>,
+    span: tracing::Span,
+    kind: RpcKind,
+    extra_headers: http::HeaderMap,
+    deadline: Option<Instant>,
+    completed: bool,
+}
+
+impl<F> ResponseFuture<F> {
+    pub(crate) fn new(inner: F, span: tracing::Span, kind: RpcKind) -> Self {
+        ResponseFuture {
+            inner: Some(inner),
+            rejected: None,
+            span,
+            kind,
+            extra_headers: http::HeaderMap::new(),
+            deadline: None,
+            completed: false,
+        }
+    }
+
+    // trailers-only response with `status`, without calling the inner service
+    pub(crate) fn rejected(status: Status, span: tracing::Span, kind: RpcKind) -> Self {
+        ResponseFuture {
+            inner: None,
+            rejected: Some(status),
+            span,
+            kind,
+            extra_headers: http::HeaderMap::new(),
+            deadline: None,
+            completed: false,
+        }
+    }
+
+    pub(crate) fn with_deadline(mut self, deadline: Option<Instant>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    // added to the response headers, and to the trailers of an error status sent after messages
+    pub(crate) fn with_extra_headers(mut self, headers: http::HeaderMap) -> Self {
+        self.extra_headers = headers;
+        self
+    }
+}
+
+impl<F, B, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<http::Response<B>, E>>,
+{
+    type Output = Result<http::Response<TracedBody<B>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let _enter = this.span.enter();
+
+        let Some(inner) = this.inner.as_pin_mut() else {
+            *this.completed = true;
+            let status = this.rejected.take().expect("ResponseFuture polled after completion");
+            record_status(this.span, *this.kind, &status);
+            let (parts, _) = status.to_http().into_parts();
+            let mut response = http::Response::from_parts(parts, ());
+            response.headers_mut().extend(std::mem::take(this.extra_headers));
+            return Poll::Ready(Ok(response.map(|()| TracedBody {
+                inner: None,
+                span: this.span.clone(),
+                kind: *this.kind,
+                done: true,
+                extra_headers: http::HeaderMap::new(),
+                deadline: None,
+            })));
+        };
+        let mut response = match inner.poll(cx) {
+            Poll::Ready(Ok(response)) => {
+                *this.completed = true;
+                response
+            }
+            Poll::Ready(Err(err)) => {
+                *this.completed = true;
+                // e.g. the client side timeout of tonic
+                if past(*this.deadline) {
+                    record_status(this.span, *this.kind, &deadline_exceeded());
+                }
+                return Poll::Ready(Err(err));
+            }
+            Poll::Pending => return Poll::Pending,
+        };
+
+        // trailers-only response, e.g. an error status returned by the handler
+        let done = match Status::from_header_map(response.headers()) {
+            Some(status) => {
+                record_final_status(this.span, *this.kind, &status, *this.deadline);
+                true
+            }
+            None => false,
+        };
+
+        let extra_headers = std::mem::take(this.extra_headers);
+        response.headers_mut().extend(extra_headers.clone());
+
+        let span = this.span.clone();
+        let kind = *this.kind;
+        let deadline = *this.deadline;
+        Poll::Ready(Ok(response.map(|inner| TracedBody {
+            inner: Some(inner),
+            span,
+            kind,
+            done,
+            extra_headers,
+            deadline,
+        })))
+    }
+}
+
+
+#[pinned_drop]
+impl<F> PinnedDrop for ResponseFuture<F> {
+    fn drop(self: Pin<&mut Self>) {
+        let this = self.project();
+        if !*this.completed {
+            record_dropped(this.span, *this.kind, *this.deadline);
+        }
+    }
+}
+
+
+/// Response body of the client and server layers, records `grpc-status` from the trailers.
+#[pin_project(PinnedDrop)]
+pub struct TracedBody<B> {
+    // None for the empty body of a rejected call
+    #[pin]
+    inner: Option<B>,
+    span: tracing::Span,
+    kind: RpcKind,
+    done: bool,
+    extra_headers: http::HeaderMap,
+    deadline: Option<Instant>,
+}
+
+impl<B: Body> Body for TracedBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_data(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let this = self.project();
+        let _enter = this.span.enter();
+        match this.inner.as_pin_mut() {
+            Some(inner) => inner.poll_data(cx),
+            None => Poll::Ready(None),
+        }
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+    ) -> Poll<Result<Option<http::HeaderMap>, Self::Error>> {
+        let this = self.project();
+        let _enter = this.span.enter();
+
+        let Some(inner) = this.inner.as_pin_mut() else {
+            return Poll::Ready(Ok(None));
+        };
+        let mut trailers = inner.poll_trailers(cx);
+        if let Poll::Ready(Ok(Some(trailers))) = &mut trailers {
+            if !*this.done {
+                if let Some(status) = Status::from_header_map(trailers) {
+                    record_final_status(this.span, *this.kind, &status, *this.deadline);
+                    *this.done = true;
+                    // the client reads the metadata of an error status from the trailers only
+                    if status.code() != Code::Ok {
+                        trailers.extend(std::mem::take(this.extra_headers));
+                    }
+                }
+            }
+        }
+        trailers
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.as_ref().is_none_or(Body::is_end_stream)
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.as_ref().map_or_else(|| SizeHint::with_exact(0), Body::size_hint)
+    }
+}
+
+
+#[pinned_drop]
+impl<B> PinnedDrop for TracedBody<B> {
+    fn drop(self: Pin<&mut Self>) {
+        let this = self.project();
+        if !*this.done {
+            record_dropped(this.span, *this.kind, *this.deadline);
+        }
+    }
+}
+
+
+// `grpc-timeout` of the request counted from now, to take before calling the inner service
+pub(crate) fn deadline(headers: &http::HeaderMap) -> Option<Instant> {
+    grpc_timeout(headers).and_then(|timeout| Instant::now().checked_add(timeout))
+}
+
+// past its deadline the call failed with DEADLINE_EXCEEDED for the client, whatever the handler returned
+fn record_final_status(span: &tracing::Span, kind: RpcKind, status: &Status, deadline: Option<Instant>) {
+    if status.code() == Code::Ok && past(deadline) {
+        record_status(span, kind, &deadline_exceeded());
+    } else {
+        record_status(span, kind, status);
+    }
+}
+
+// a server dropping the call before sending the status means the client is gone,
+// after a timeout when the deadline has passed
+fn record_dropped(span: &tracing::Span, kind: RpcKind, deadline: Option<Instant>) {
+    if kind != RpcKind::Server {
+        return;
+    }
+    if past(deadline) {
+        record_status(span, kind, &deadline_exceeded());
+    } else {
+        record_cancelled(span);
+    }
+}
+
+fn past(deadline: Option<Instant>) -> bool {
+    deadline.is_some_and(|deadline| Instant::now() > deadline)
+}
+
+fn deadline_exceeded() -> Status {
+    Status::deadline_exceeded("grpc-timeout deadline exceeded")
+}
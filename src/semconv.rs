@@ -0,0 +1,296 @@
+//! OpenTelemetry RPC semantic conventions for gRPC,
+//! see <https://opentelemetry.io/docs/specs/semconv/rpc/grpc/>
+
+use std::time::Duration;
+
+use opentelemetry::KeyValue;
+
+use tonic::{Code, Status};
+use tracing::field::Empty;
+
+use crate::trust::remote_addr;
+
+
+pub const RPC_SYSTEM: &str = "rpc.system";
+pub const RPC_SERVICE: &str = "rpc.service";
+pub const RPC_METHOD: &str = "rpc.method";
+pub const RPC_GRPC_STATUS_CODE: &str = "rpc.grpc.status_code";
+/// Timeout of the call in milliseconds from `grpc-timeout`, i.e. the remaining deadline when the server receives it.
+pub const RPC_GRPC_TIMEOUT_MS: &str = "rpc.grpc.timeout_ms";
+pub const SERVER_ADDRESS: &str = "server.address";
+pub const SERVER_PORT: &str = "server.port";
+pub const NETWORK_PEER_ADDRESS: &str = "network.peer.address";
+pub const MESSAGE_TYPE: &str = "message.type";
+pub const MESSAGE_ID: &str = "message.id";
+pub const MESSAGE_UNCOMPRESSED_SIZE: &str = "message.uncompressed_size";
+
+const GRPC_TIMEOUT_HEADER: &str = "grpc-timeout";
+const OTEL_STATUS_CODE: &str = "otel.status_code";
+const OTEL_STATUS_MESSAGE: &str = "otel.status_message";
+
+
+/// Split a gRPC path into service and method.
+///
+/// "/package.Service/Method" -> Some(("package.Service", "Method"))
+pub fn parse_grpc_path(path: &str) -> Option<(&str, &str)> {
+    let (service, method) = path.strip_prefix('/')?.split_once('/')?;
+    if service.is_empty() || method.is_empty() || method.contains('/') {
+        return None;
+    }
+    Some((service, method))
+}
+
+/// `rpc.system`, `rpc.service` and `rpc.method` for a gRPC path,
+/// for spans created with the OTel api directly.
+pub fn rpc_attributes(path: &str) -> Vec<KeyValue> {
+    let mut attributes = vec![KeyValue::new(RPC_SYSTEM, "grpc")];
+    if let Some((service, method)) = parse_grpc_path(path) {
+        attributes.push(KeyValue::new(RPC_SERVICE, service.to_string()));
+        attributes.push(KeyValue::new(RPC_METHOD, method.to_string()));
+    }
+    attributes
+}
+
+/// Read `grpc-status` from response headers (trailers-only response) or trailers.
+pub fn grpc_status_code(headers: &http::HeaderMap) -> Option<Code> {
+    let status = headers.get("grpc-status")?.to_str().ok()?.parse::<i32>().ok()?;
+    Some(Code::from_i32(status))
+}
+
+/// Read `grpc-timeout`, set by the client with `tonic::Request::set_timeout`.
+pub fn grpc_timeout(headers: &http::HeaderMap) -> Option<Duration> {
+    parse_grpc_timeout(headers.get(GRPC_TIMEOUT_HEADER)?.to_str().ok()?)
+}
+
+/// Parse a `grpc-timeout` value, at most 8 digits followed by the unit.
+///
+/// "100m" -> 100ms, "5S" -> 5s, "1H" -> 1h
+pub fn parse_grpc_timeout(value: &str) -> Option<Duration> {
+    if !value.is_ascii() {
+        return None;
+    }
+    let (digits, unit) = value.split_at(value.len().checked_sub(1)?);
+    if digits.is_empty() || digits.len() > 8 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let amount: u64 = digits.parse().ok()?;
+    match unit {
+        "H" => Some(Duration::from_secs(amount * 60 * 60)),
+        "M" => Some(Duration::from_secs(amount * 60)),
+        "S" => Some(Duration::from_secs(amount)),
+        "m" => Some(Duration::from_millis(amount)),
+        "u" => Some(Duration::from_micros(amount)),
+        "n" => Some(Duration::from_nanos(amount)),
+        _ => None,
+    }
+}
+
+/// Status codes which mark a server span as failed, the server is not at fault for the others
+/// (e.g. `NotFound`, `InvalidArgument`). On the client every non-OK code is an error.
+pub fn is_server_error(code: Code) -> bool {
+    matches!(
+        code,
+        Code::Unknown
+            | Code::DeadlineExceeded
+            | Code::Unimplemented
+            | Code::Internal
+            | Code::Unavailable
+            | Code::DataLoss
+    )
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RpcKind {
+    Client,
+    Server,
+}
+
+// span name is "package.Service/Method", falls back to the raw path for non-gRPC requests
+pub(crate) fn rpc_span<B>(kind: RpcKind, request: &http::Request<B>) -> tracing::Span {
+    let path = request.uri().path();
+    let name = path.strip_prefix('/').unwrap_or(path);
+    let (service, method) = match parse_grpc_path(path) {
+        Some((service, method)) => (Some(service), Some(method)),
+        None => (None, None),
+    };
+    let span = new_rpc_span(kind, name, service, method);
+
+    if let Some((host, port)) = server_address(request) {
+        span.record(SERVER_ADDRESS, host.as_str());
+        if let Some(port) = port {
+            span.record(SERVER_PORT, port as i64);
+        }
+    }
+    if let Some(timeout) = grpc_timeout(request.headers()) {
+        span.record(RPC_GRPC_TIMEOUT_MS, timeout.as_millis() as i64);
+    }
+    if kind == RpcKind::Server {
+        if let Some(peer) = remote_addr(request.extensions()) {
+            span.record(NETWORK_PEER_ADDRESS, tracing::field::display(peer.ip()));
+        }
+    }
+
+    span
+}
+
+// span with every rpc field declared, the ones only known later left empty
+pub(crate) fn new_rpc_span(kind: RpcKind, name: &str, service: Option<&str>, method: Option<&str>) -> tracing::Span {
+    match kind {
+        RpcKind::Client => tracing::info_span!(
+            "grpc.client",
+            otel.name = name,
+            otel.kind = "client",
+            rpc.system = "grpc",
+            rpc.service = service,
+            rpc.method = method,
+            rpc.grpc.status_code = Empty,
+            rpc.grpc.timeout_ms = Empty,
+            otel.status_code = Empty,
+            otel.status_message = Empty,
+            server.address = Empty,
+            server.port = Empty,
+            network.peer.address = Empty,
+        ),
+        RpcKind::Server => tracing::info_span!(
+            "grpc.server",
+            otel.name = name,
+            otel.kind = "server",
+            rpc.system = "grpc",
+            rpc.service = service,
+            rpc.method = method,
+            rpc.grpc.status_code = Empty,
+            rpc.grpc.timeout_ms = Empty,
+            otel.status_code = Empty,
+            otel.status_message = Empty,
+            server.address = Empty,
+            server.port = Empty,
+            network.peer.address = Empty,
+        ),
+    }
+}
+
+// set rpc.grpc.status_code, span status, and an exception event for non-OK statuses
+pub(crate) fn record_status(span: &tracing::Span, kind: RpcKind, status: &Status) {
+    let code = status.code();
+    span.record(RPC_GRPC_STATUS_CODE, code as i64);
+    if code == Code::Ok {
+        return;
+    }
+
+    let error = match kind {
+        RpcKind::Client => true,
+        RpcKind::Server => is_server_error(code),
+    };
+    if error {
+        span.record(OTEL_STATUS_CODE, "ERROR");
+        if !status.message().is_empty() {
+            span.record(OTEL_STATUS_MESSAGE, status.message());
+        }
+    }
+    // tracing-opentelemetry attaches events to the entered span, not the explicit parent,
+    // and the span is not entered when a dropped call is recorded
+    span.in_scope(|| {
+        if error {
+            tracing::error!(
+                exception.r#type = "tonic::Status",
+                exception.message = status.message(),
+                rpc.grpc.status_code = code as i64,
+                "exception",
+            );
+        } else {
+            tracing::info!(
+                exception.r#type = "tonic::Status",
+                exception.message = status.message(),
+                rpc.grpc.status_code = code as i64,
+                "exception",
+            );
+        }
+    });
+}
+
+// the server future or body was dropped before the status was sent, i.e. the client went away
+pub(crate) fn record_cancelled(span: &tracing::Span) {
+    span.record(RPC_GRPC_STATUS_CODE, Code::Cancelled as i64);
+    span.in_scope(|| tracing::info!("cancelled"));
+}
+
+// uri authority when present (http2 :authority), else the Host header
+fn server_address<B>(request: &http::Request<B>) -> Option<(String, Option<u16>)> {
+    let authority = match request.uri().authority() {
+        Some(authority) => authority.clone(),
+        None => request.headers().get(http::header::HOST)?.to_str().ok()?.parse().ok()?,
+    };
+    Some((authority.host().to_string(), authority.port_u16()))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use tonic::Code;
+
+    use std::time::Duration;
+
+    use super::{grpc_status_code, is_server_error, parse_grpc_path, parse_grpc_timeout, rpc_attributes, server_address};
+
+    #[test]
+    fn timeout() {
+        assert_eq!(parse_grpc_timeout("100m"), Some(Duration::from_millis(100)));
+        assert_eq!(parse_grpc_timeout("5S"), Some(Duration::from_secs(5)));
+        assert_eq!(parse_grpc_timeout("2M"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_grpc_timeout("1H"), Some(Duration::from_secs(3600)));
+        assert_eq!(parse_grpc_timeout("99999999u"), Some(Duration::from_micros(99_999_999)));
+        assert_eq!(parse_grpc_timeout("10n"), Some(Duration::from_nanos(10)));
+        assert_eq!(parse_grpc_timeout("123456789m"), None);
+        assert_eq!(parse_grpc_timeout("m"), None);
+        assert_eq!(parse_grpc_timeout("10s"), None);
+        assert_eq!(parse_grpc_timeout("-1S"), None);
+        assert_eq!(parse_grpc_timeout(""), None);
+    }
+
+    #[test]
+    fn parse_path() {
+        assert_eq!(
+            parse_grpc_path("/helloworld.Greeter/SayHello"),
+            Some(("helloworld.Greeter", "SayHello"))
+        );
+        assert_eq!(parse_grpc_path("/helloworld.Greeter"), None);
+        assert_eq!(parse_grpc_path("/a/b/c"), None);
+        assert_eq!(parse_grpc_path("//SayHello"), None);
+        assert_eq!(rpc_attributes("/health").len(), 1);
+        assert_eq!(rpc_attributes("/grpc.health.v1.Health/Check").len(), 3);
+    }
+
+    #[test]
+    fn status_code() {
+        let mut headers = http::HeaderMap::new();
+        assert_eq!(grpc_status_code(&headers), None);
+        headers.insert("grpc-status", "14".parse().unwrap());
+        assert_eq!(grpc_status_code(&headers), Some(Code::Unavailable));
+    }
+
+    #[test]
+    fn server_error() {
+        assert!(is_server_error(Code::Internal));
+        assert!(is_server_error(Code::DataLoss));
+        assert!(!is_server_error(Code::Ok));
+        assert!(!is_server_error(Code::NotFound));
+        assert!(!is_server_error(Code::PermissionDenied));
+    }
+
+    #[test]
+    fn address() {
+        let request = http::Request::builder()
+            .uri("http://example.com:50051/helloworld.Greeter/SayHello")
+            .body(())
+            .unwrap();
+        assert_eq!(server_address(&request), Some(("example.com".to_string(), Some(50051))));
+
+        let request = http::Request::builder()
+            .uri("/helloworld.Greeter/SayHello")
+            .header("host", "localhost:8080")
+            .body(())
+            .unwrap();
+        assert_eq!(server_address(&request), Some(("localhost".to_string(), Some(8080))));
+    }
+}
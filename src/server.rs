@@ -0,0 +1,778 @@
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll};
+
+use opentelemetry::baggage::BaggageExt;
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry::trace::{TraceContextExt, TraceId};
+use opentelemetry::KeyValue;
+
+use http::header::{HeaderName, HeaderValue};
+use http::request::Parts;
+use http::HeaderMap;
+
+use tower_layer::Layer;
+use tower_service::Service;
+
+// extend tracing::Span with set_parent() & context()
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::baggage::baggage_cx;
+use crate::encoding::{EncodingExtractor, Encodings};
+use crate::filter::MethodFilter;
+use crate::grpc_trace_bin::{or_grpc_trace_bin, GRPC_TRACE_BIN_HEADER};
+use crate::headers::with_metadata;
+use crate::limits::ExtractLimits;
+use crate::propagator::Propagator;
+use crate::response::{deadline, ResponseFuture, TracedBody};
+use crate::semconv::{rpc_span, RpcKind};
+use crate::span_ext::set_attributes;
+use crate::trust::{TrustPolicy, TrustSelector};
+use crate::HeaderInjector;
+
+
+/// Server `Layer` opening a span per RPC, parented on the context sent by the client.
+///
+/// pre-requisite, unless [`with_propagator`](Self::with_propagator) is used:
+/// global::set_text_map_propagator(TraceContextPropagator::new());
+///
+/// ```ignore
+/// Server::builder()
+///     .layer(TraceExtractLayer::new())
+///     .add_service(GreeterServer::new(greeter))
+///     .serve(addr)
+///     .await?;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct TraceExtractLayer {
+    options: Arc<Options>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Options {
+    propagator: Propagator,
+    filter: Option<MethodFilter>,
+    baggage_attributes: Vec<String>,
+    trace_id_header: Option<HeaderName>,
+    traceparent_header: bool,
+    trust: TrustSelector,
+    limits: Option<ExtractLimits>,
+    // headers the limits apply to, set by `Layer::layer`
+    limited_fields: Vec<String>,
+    encodings: Encodings,
+    grpc_trace_bin: bool,
+}
+
+impl Options {
+    // headers the limits apply to: those of the propagator, and baggage read for the attributes
+    fn fields_to_limit(&self) -> Vec<String> {
+        let mut fields: Vec<String> = self.propagator.with(|propagator| propagator.fields().map(str::to_string).collect());
+        if !fields.iter().any(|field| field == "baggage") {
+            fields.push("baggage".to_string());
+        }
+        if self.grpc_trace_bin {
+            fields.push(GRPC_TRACE_BIN_HEADER.to_string());
+        }
+        fields
+    }
+
+    // ids of the server span to return to the client, empty when not configured or not traced
+    fn response_headers(&self, span: &tracing::Span) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if self.trace_id_header.is_none() && !self.traceparent_header {
+            return headers;
+        }
+        let cx = span.context();
+        let trace_id = cx.span().span_context().trace_id();
+        if trace_id == TraceId::INVALID {
+            return headers;
+        }
+
+        if let Some(name) = &self.trace_id_header {
+            if let Ok(value) = HeaderValue::from_str(&trace_id.to_string()) {
+                headers.insert(name.clone(), value);
+            }
+        }
+        if self.traceparent_header {
+            TraceContextPropagator::new().inject_context(&cx, &mut HeaderInjector(&mut headers));
+        }
+        headers
+    }
+}
+
+impl TraceExtractLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Extract with this propagator instead of the global one.
+    pub fn with_propagator<P>(mut self, propagator: P) -> Self
+    where
+        P: TextMapPropagator + Send + Sync + 'static,
+    {
+        Arc::make_mut(&mut self.options).propagator = Propagator::new(propagator);
+        self
+    }
+
+    /// What to do with the context sent by the client, [`TrustPolicy::Trust`] by default.
+    /// Baggage attributes are not affected.
+    pub fn with_trust_policy(mut self, policy: TrustPolicy) -> Self {
+        Arc::make_mut(&mut self.options).trust = TrustSelector::Fixed(policy);
+        self
+    }
+
+    /// Choose the [`TrustPolicy`] per request, e.g. by peer address or mTLS identity.
+    ///
+    /// ```ignore
+    /// // trust internal callers only
+    /// .with_trust_policy_fn(|parts| match peer_addr(parts) {
+    ///     Some(addr) if addr.ip().is_loopback() => TrustPolicy::Trust,
+    ///     _ => TrustPolicy::LinkOnly,
+    /// })
+    ///
+    /// // trust clients presenting a certificate, with the `tls` feature
+    /// .with_trust_policy_fn(|parts| match parts.extensions.get::<TlsConnectInfo<TcpConnectInfo>>() {
+    ///     Some(info) if info.peer_certs().is_some() => TrustPolicy::Trust,
+    ///     _ => TrustPolicy::Ignore,
+    /// })
+    /// ```
+    pub fn with_trust_policy_fn<F>(mut self, select: F) -> Self
+    where
+        F: Fn(&Parts) -> TrustPolicy + Send + Sync + 'static,
+    {
+        Arc::make_mut(&mut self.options).trust = TrustSelector::Select(Arc::new(select));
+        self
+    }
+
+    /// Open spans only for the methods traced by `filter`, e.g. to skip health checks.
+    pub fn with_filter(mut self, filter: MethodFilter) -> Self {
+        Arc::make_mut(&mut self.options).filter = Some(filter);
+        self
+    }
+
+    /// Copy these baggage entries sent by the client into attributes of the server span,
+    /// e.g. `.with_baggage_attributes(["tenant.id"])`.
+    pub fn with_baggage_attributes<I>(mut self, keys: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        Arc::make_mut(&mut self.options).baggage_attributes = keys.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Return the trace id of the server span to the client in this response header,
+    /// e.g. [`TRACE_ID_HEADER`](crate::TRACE_ID_HEADER).
+    /// Read it back with [`trace_id_from_status`](crate::trace_id_from_status).
+    pub fn with_trace_id_header(mut self, name: HeaderName) -> Self {
+        Arc::make_mut(&mut self.options).trace_id_header = Some(name);
+        self
+    }
+
+    /// Return the W3C `traceparent` (and `tracestate`) of the server span to the client in the response headers.
+    pub fn with_traceparent_header(mut self) -> Self {
+        Arc::make_mut(&mut self.options).traceparent_header = true;
+        self
+    }
+
+    /// Limit the size of the trace context headers before extracting them,
+    /// a request rejected by the limits gets `INVALID_ARGUMENT` without reaching the service.
+    /// The headers are those of the propagator when the layer wraps the service.
+    pub fn with_limits(mut self, limits: ExtractLimits) -> Self {
+        Arc::make_mut(&mut self.options).limits = Some(limits);
+        self
+    }
+
+    /// Decode the values of these keys, as encoded by [`TraceInjectLayer::with_encodings`](crate::TraceInjectLayer::with_encodings)
+    /// or [`TraceInjectInterceptor::with_encodings`](crate::TraceInjectInterceptor::with_encodings).
+    /// Baggage attributes are decoded too.
+    pub fn with_encodings(mut self, encodings: Encodings) -> Self {
+        Arc::make_mut(&mut self.options).encodings = encodings;
+        self
+    }
+
+    /// Also read the span context from the binary `grpc-trace-bin` entry when the propagator finds none,
+    /// e.g. from clients using gRPC's OpenCensus tracing, see [`grpc_trace_bin`](crate::grpc_trace_bin).
+    pub fn with_grpc_trace_bin(mut self) -> Self {
+        Arc::make_mut(&mut self.options).grpc_trace_bin = true;
+        self
+    }
+}
+
+impl<S> Layer<S> for TraceExtractLayer {
+    type Service = TraceExtractService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        let mut options = self.options.clone();
+        if options.limits.is_some() {
+            Arc::make_mut(&mut options).limited_fields = options.fields_to_limit();
+        }
+        TraceExtractService { inner, options }
+    }
+}
+
+
+/// Service created by [`TraceExtractLayer`].
+#[derive(Debug, Clone)]
+pub struct TraceExtractService<S> {
+    inner: S,
+    options: Arc<Options>,
+}
+
+impl<S, B, ResBody> Service<http::Request<B>> for TraceExtractService<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<ResBody>>,
+{
+    type Response = http::Response<TracedBody<ResBody>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        if let Some(filter) = &self.options.filter {
+            if !filter.traces(request.uri().path()) {
+                return ResponseFuture::new(self.inner.call(request), tracing::Span::none(), RpcKind::Server);
+            }
+        }
+
+        let span = rpc_span(RpcKind::Server, &request);
+        let (mut parts, body) = request.into_parts();
+        if let Some(limits) = &self.options.limits {
+            let fields = self.options.limited_fields.iter().map(String::as_str);
+            let limited = span.in_scope(|| limits.apply(&mut parts.headers, fields));
+            if let Err(violation) = limited {
+                let response_headers = self.options.response_headers(&span);
+                return ResponseFuture::rejected(violation.into(), span, RpcKind::Server)
+                    .with_extra_headers(response_headers);
+            }
+        }
+        let keys = &self.options.baggage_attributes;
+        let (remote_cx, baggage_cx) = with_metadata(&mut parts.headers, |metadata| {
+            let extractor = EncodingExtractor::new(metadata, &self.options.encodings);
+            let mut remote_cx = self.options.propagator.with(|propagator| propagator.extract(&extractor));
+            if self.options.grpc_trace_bin {
+                remote_cx = or_grpc_trace_bin(remote_cx, metadata);
+            }
+            (remote_cx, (!keys.is_empty()).then(|| baggage_cx(&extractor)))
+        });
+        self.options.trust.select(&parts).apply(&span, remote_cx);
+
+        if let Some(cx) = baggage_cx {
+            set_attributes(
+                &span,
+                keys.iter().filter_map(|key| {
+                    let value = cx.baggage().get(key.clone())?;
+                    Some(KeyValue::new(key.clone(), value.clone()))
+                }),
+            );
+        }
+
+        let request = http::Request::from_parts(parts, body);
+        let response_headers = self.options.response_headers(&span);
+        let deadline = deadline(request.headers());
+        ResponseFuture::new(span.in_scope(|| self.inner.call(request)), span, RpcKind::Server)
+            .with_extra_headers(response_headers)
+            .with_deadline(deadline)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::future::{Future, Ready};
+    use std::pin::Pin;
+    use std::task::Poll;
+    use std::time::Duration;
+
+    use opentelemetry::baggage::BaggageExt;
+    use opentelemetry::{global, Context, Value};
+    use opentelemetry::propagation::TextMapPropagator;
+    use opentelemetry::sdk::propagation::TraceContextPropagator;
+    use opentelemetry::trace::{
+        Span, SpanContext, SpanId, SpanKind, Status, TraceContextExt, TraceFlags, TraceId, TraceState, Tracer,
+        TracerProvider as _,
+    };
+    use http::header::HeaderName;
+    use http_body::Body;
+    use tonic::body::{empty_body, BoxBody};
+    use tonic::metadata::MetadataMap;
+    use tonic::transport::server::Connected;
+    use tower::{service_fn, Layer, Service, ServiceBuilder, ServiceExt};
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    use super::TraceExtractLayer;
+    use crate::grpc_trace_bin::GrpcTraceBinPropagator;
+    use crate::testing::{attribute, SpanRecorder, TenantPropagator, TrailersBody};
+    use crate::{
+        trace_id_from_status, Encoding, Encodings, ExtractLimits, LimitAction, MethodFilter, TrustPolicy, TRACE_ID_HEADER,
+    };
+    use crate::MetadataInjector;
+
+    fn grpc_request(metadata: MetadataMap) -> http::Request<()> {
+        let mut request = http::Request::builder()
+            .uri("http://localhost:50051/helloworld.Greeter/SayHello")
+            .body(())
+            .unwrap();
+        *request.headers_mut() = metadata.into_headers();
+        request
+    }
+
+    fn status_response(code: &str) -> http::Response<BoxBody> {
+        http::Response::builder()
+            .header("grpc-status", code)
+            .body(empty_body())
+            .unwrap()
+    }
+
+    async fn respond(response: http::Response<BoxBody>) -> Result<http::Response<BoxBody>, Infallible> {
+        Ok(response)
+    }
+
+    #[tokio::test]
+    async fn extract_parent() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let recorder = SpanRecorder::default();
+        let (provider, subscriber) = recorder.subscriber();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let client_span = provider.tracer("test").start("client-span");
+        let client_span_context = client_span.span_context().clone();
+        let cx = Context::current_with_span(client_span);
+        let mut metadata = MetadataMap::new();
+        global::get_text_map_propagator(|propagator| {
+            propagator.inject_context(&cx, &mut MetadataInjector(&mut metadata))
+        });
+
+        ServiceBuilder::new()
+            .layer(TraceExtractLayer::new())
+            .service(service_fn(|_request| respond(status_response("0"))))
+            .oneshot(grpc_request(metadata))
+            .await
+            .unwrap();
+
+        let span = recorder.span("helloworld.Greeter/SayHello");
+        assert_eq!(span.span_kind, SpanKind::Server);
+        assert_eq!(span.span_context.trace_id(), client_span_context.trace_id());
+        assert_eq!(span.parent_span_id, client_span_context.span_id());
+        assert_eq!(attribute(&span, "rpc.system"), Some(Value::from("grpc")));
+        assert_eq!(attribute(&span, "rpc.service"), Some(Value::from("helloworld.Greeter")));
+        assert_eq!(attribute(&span, "rpc.method"), Some(Value::from("SayHello")));
+        assert_eq!(attribute(&span, "server.address"), Some(Value::from("localhost")));
+        assert_eq!(attribute(&span, "server.port"), Some(Value::I64(50051)));
+    }
+
+    #[tokio::test]
+    async fn peer_address() {
+        let recorder = SpanRecorder::default();
+        let (_provider, subscriber) = recorder.subscriber();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let _client = tokio::net::TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut request = grpc_request(MetadataMap::new());
+        request.extensions_mut().insert(stream.connect_info());
+
+        ServiceBuilder::new()
+            .layer(TraceExtractLayer::new())
+            .service(service_fn(|_request| respond(status_response("0"))))
+            .oneshot(request)
+            .await
+            .unwrap();
+
+        let span = recorder.span("helloworld.Greeter/SayHello");
+        assert_eq!(attribute(&span, "network.peer.address"), Some(Value::from("127.0.0.1")));
+    }
+
+    #[tokio::test]
+    async fn filtered_method() {
+        let recorder = SpanRecorder::default();
+        let (_provider, subscriber) = recorder.subscriber();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let mut service = ServiceBuilder::new()
+            .layer(TraceExtractLayer::new().with_filter(MethodFilter::exclude(["grpc.health.v1.Health/*"])))
+            .service(service_fn(|_request| respond(status_response("0"))));
+        let mut health_check = grpc_request(MetadataMap::new());
+        *health_check.uri_mut() = "http://localhost:50051/grpc.health.v1.Health/Check".parse().unwrap();
+        service.ready().await.unwrap().call(health_check).await.unwrap();
+        service.ready().await.unwrap().call(grpc_request(MetadataMap::new())).await.unwrap();
+
+        let names: Vec<_> = recorder.spans().into_iter().map(|span| span.name).collect();
+        assert_eq!(names, ["helloworld.Greeter/SayHello"]);
+    }
+
+    #[tokio::test]
+    async fn deadline_exceeded() {
+        let recorder = SpanRecorder::default();
+        let (_provider, subscriber) = recorder.subscriber();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let mut metadata = MetadataMap::new();
+        metadata.insert("grpc-timeout", "1m".parse().unwrap());
+        ServiceBuilder::new()
+            .layer(TraceExtractLayer::new())
+            .service(service_fn(|_request| {
+                std::thread::sleep(Duration::from_millis(5));
+                respond(status_response("0"))
+            }))
+            .oneshot(grpc_request(metadata.clone()))
+            .await
+            .unwrap();
+
+        let span = recorder.span("helloworld.Greeter/SayHello");
+        assert_eq!(attribute(&span, "rpc.grpc.timeout_ms"), Some(Value::I64(1)));
+        assert_eq!(attribute(&span, "rpc.grpc.status_code"), Some(Value::I64(4)));
+        assert_eq!(span.status, Status::error("grpc-timeout deadline exceeded"));
+
+        // handler dropped past the deadline, while another span is current
+        let mut handler = ServiceBuilder::new()
+            .layer(TraceExtractLayer::new())
+            .service(service_fn(|_request: http::Request<()>| {
+                std::future::pending::<Result<http::Response<BoxBody>, Infallible>>()
+            }))
+            .oneshot(grpc_request(metadata));
+        std::future::poll_fn(|cx| {
+            assert!(Pin::new(&mut handler).poll(cx).is_pending());
+            Poll::Ready(())
+        })
+        .await;
+        std::thread::sleep(Duration::from_millis(5));
+        tracing::info_span!("unrelated").in_scope(|| drop(handler));
+
+        let spans = recorder.spans();
+        let dropped = spans.iter().rev().find(|span| span.name == "helloworld.Greeter/SayHello").unwrap();
+        assert_eq!(attribute(dropped, "rpc.grpc.status_code"), Some(Value::I64(4)));
+        assert_eq!(dropped.status, Status::error("grpc-timeout deadline exceeded"));
+        assert!(dropped.events.iter().any(|event| event.name == "exception"));
+        let unrelated = recorder.span("unrelated");
+        assert!(unrelated.events.is_empty());
+    }
+
+    #[tokio::test]
+    async fn trust_policy() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let recorder = SpanRecorder::default();
+        let (_provider, subscriber) = recorder.subscriber();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let mut metadata = MetadataMap::new();
+        metadata.insert("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".parse().unwrap());
+        ServiceBuilder::new()
+            .layer(TraceExtractLayer::new().with_trust_policy_fn(|parts| {
+                match parts.headers.contains_key("x-internal") {
+                    true => TrustPolicy::Trust,
+                    false => TrustPolicy::LinkOnly,
+                }
+            }))
+            .service(service_fn(|_request| respond(status_response("0"))))
+            .oneshot(grpc_request(metadata))
+            .await
+            .unwrap();
+
+        let span = recorder.span("helloworld.Greeter/SayHello");
+        assert_eq!(span.parent_span_id, SpanId::INVALID);
+        assert_ne!(span.span_context.trace_id().to_string(), "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(span.links.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn explicit_propagator() {
+        let recorder = SpanRecorder::default();
+        let (_provider, subscriber) = recorder.subscriber();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let span_context = SpanContext::new(
+            TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap(),
+            SpanId::from_hex("00f067aa0ba902b7").unwrap(),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        );
+        let mut metadata = MetadataMap::new();
+        TraceContextPropagator::new().inject_context(
+            &Context::new().with_remote_span_context(span_context.clone()),
+            &mut MetadataInjector(&mut metadata),
+        );
+
+        ServiceBuilder::new()
+            .layer(TraceExtractLayer::new().with_propagator(TraceContextPropagator::new()))
+            .service(service_fn(|_request| respond(status_response("0"))))
+            .oneshot(grpc_request(metadata))
+            .await
+            .unwrap();
+
+        let span = recorder.span("helloworld.Greeter/SayHello");
+        assert_eq!(span.span_context.trace_id(), span_context.trace_id());
+        assert_eq!(span.parent_span_id, span_context.span_id());
+    }
+
+    #[tokio::test]
+    async fn baggage_attributes() {
+        let recorder = SpanRecorder::default();
+        let (_provider, subscriber) = recorder.subscriber();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let mut metadata = MetadataMap::new();
+        metadata.insert("baggage", "tenant.id=acme,user.id=42".parse().unwrap());
+
+        ServiceBuilder::new()
+            .layer(TraceExtractLayer::new().with_baggage_attributes(["tenant.id", "region"]))
+            .service(service_fn(|_request| respond(status_response("0"))))
+            .oneshot(grpc_request(metadata))
+            .await
+            .unwrap();
+
+        let span = recorder.span("helloworld.Greeter/SayHello");
+        assert_eq!(attribute(&span, "tenant.id"), Some(Value::from("acme")));
+        assert_eq!(attribute(&span, "user.id"), None);
+        assert_eq!(attribute(&span, "region"), None);
+    }
+
+    #[tokio::test]
+    async fn grpc_trace_bin() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let recorder = SpanRecorder::default();
+        let (_provider, subscriber) = recorder.subscriber();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let span_context = SpanContext::new(
+            TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap(),
+            SpanId::from_hex("00f067aa0ba902b7").unwrap(),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        );
+        let mut metadata = MetadataMap::new();
+        GrpcTraceBinPropagator::new()
+            .inject_context(&Context::new().with_remote_span_context(span_context.clone()), &mut metadata);
+
+        for layer in [TraceExtractLayer::new(), TraceExtractLayer::new().with_grpc_trace_bin()] {
+            ServiceBuilder::new()
+                .layer(layer)
+                .service(service_fn(|_request| respond(status_response("0"))))
+                .oneshot(grpc_request(metadata.clone()))
+                .await
+                .unwrap();
+        }
+
+        let spans = recorder.spans();
+        assert_ne!(spans[0].span_context.trace_id(), span_context.trace_id());
+        assert_eq!(spans[1].span_context.trace_id(), span_context.trace_id());
+        assert_eq!(spans[1].parent_span_id, span_context.span_id());
+    }
+
+    #[tokio::test]
+    async fn encodings() {
+        let recorder = SpanRecorder::default();
+        let (_provider, subscriber) = recorder.subscriber();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        // as sent by TraceInjectLayer::with_encodings
+        let mut metadata = MetadataMap::new();
+        metadata.insert("x-tenant", "Z%C3%BCrich%20NY".parse().unwrap());
+
+        let encodings = Encodings::new().with_key("x-tenant", Encoding::Percent);
+        let layer = TraceExtractLayer::new().with_propagator(TenantPropagator::new()).with_encodings(encodings);
+        ServiceBuilder::new()
+            .layer(layer)
+            .service(service_fn(|request: http::Request<()>| {
+                // the service gets the headers as sent, the propagator the decoded value
+                assert_eq!(request.headers()["x-tenant"], "Z%C3%BCrich%20NY");
+                let cx = tracing::Span::current().context();
+                assert_eq!(cx.baggage().get("tenant"), Some(&Value::from("Zürich NY")));
+                respond(status_response("0"))
+            }))
+            .oneshot(grpc_request(metadata))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn extract_limits() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let recorder = SpanRecorder::default();
+        let (_provider, subscriber) = recorder.subscriber();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let mut metadata = MetadataMap::new();
+        metadata.insert("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".parse().unwrap());
+        metadata.insert("baggage", "tenant.id=acme,region=eu,user.id=42".parse().unwrap());
+
+        let limits = ExtractLimits::new().with_max_baggage_members(2).with_action(LimitAction::Truncate);
+        ServiceBuilder::new()
+            .layer(TraceExtractLayer::new().with_baggage_attributes(["tenant.id", "user.id"]).with_limits(limits))
+            .service(service_fn(|request: http::Request<()>| {
+                assert_eq!(request.headers().get("baggage").unwrap(), "tenant.id=acme,region=eu");
+                respond(status_response("0"))
+            }))
+            .oneshot(grpc_request(metadata.clone()))
+            .await
+            .unwrap();
+        let span = recorder.span("helloworld.Greeter/SayHello");
+        assert_eq!(span.span_context.trace_id().to_string(), "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(attribute(&span, "tenant.id"), Some(Value::from("acme")));
+        assert_eq!(attribute(&span, "user.id"), None);
+
+        let limits = ExtractLimits::new().with_max_baggage_members(2).with_action(LimitAction::Reject);
+        let response = ServiceBuilder::new()
+            .layer(TraceExtractLayer::new().with_limits(limits))
+            .service(service_fn(|_request: http::Request<()>| -> Ready<Result<http::Response<BoxBody>, Infallible>> {
+                panic!("rejected calls don't reach the service")
+            }))
+            .oneshot(grpc_request(metadata))
+            .await
+            .unwrap();
+        assert_eq!(response.headers().get("grpc-status").unwrap(), "3");
+        assert!(response.into_body().is_end_stream());
+
+        let spans = recorder.spans();
+        let rejected = spans.last().unwrap();
+        assert_eq!(attribute(rejected, "rpc.grpc.status_code"), Some(Value::I64(3)));
+        assert_ne!(rejected.span_context.trace_id().to_string(), "4bf92f3577b34da6a3ce929d0e0e4736");
+    }
+
+    #[test]
+    fn limited_fields() {
+        let layer = TraceExtractLayer::new()
+            .with_propagator(TraceContextPropagator::new())
+            .with_grpc_trace_bin()
+            .with_limits(ExtractLimits::new());
+        // read once, when the service is created
+        assert_eq!(layer.layer(()).options.limited_fields, ["traceparent", "tracestate", "baggage", "grpc-trace-bin"]);
+        assert!(TraceExtractLayer::new().layer(()).options.limited_fields.is_empty());
+    }
+
+    #[tokio::test]
+    async fn trailers_only_status() {
+        let recorder = SpanRecorder::default();
+        let (_provider, subscriber) = recorder.subscriber();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        ServiceBuilder::new()
+            .layer(TraceExtractLayer::new())
+            .service(service_fn(|_request| respond(status_response("5"))))
+            .oneshot(grpc_request(MetadataMap::new()))
+            .await
+            .unwrap();
+
+        let span = recorder.span("helloworld.Greeter/SayHello");
+        assert_eq!(attribute(&span, "rpc.grpc.status_code"), Some(Value::I64(5)));
+        // NotFound is the caller's fault, not a server error
+        assert_eq!(span.status, Status::Unset);
+    }
+
+    #[tokio::test]
+    async fn server_error_status() {
+        let recorder = SpanRecorder::default();
+        let (_provider, subscriber) = recorder.subscriber();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        ServiceBuilder::new()
+            .layer(TraceExtractLayer::new())
+            .service(service_fn(|_request| {
+                respond(tonic::Status::internal("database is gone").to_http())
+            }))
+            .oneshot(grpc_request(MetadataMap::new()))
+            .await
+            .unwrap();
+
+        let span = recorder.span("helloworld.Greeter/SayHello");
+        assert_eq!(attribute(&span, "rpc.grpc.status_code"), Some(Value::I64(13)));
+        assert_eq!(span.status, Status::error("database is gone"));
+        let event = span.events.iter().find(|event| event.name == "exception").unwrap();
+        assert!(event
+            .attributes
+            .iter()
+            .any(|kv| kv.key.as_str() == "exception.message" && kv.value == Value::from("database is gone")));
+        assert!(event.attributes.iter().any(|kv| kv.key.as_str() == "exception.type"));
+    }
+
+    #[tokio::test]
+    async fn trace_id_header() {
+        let recorder = SpanRecorder::default();
+        let (_provider, subscriber) = recorder.subscriber();
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let layer = TraceExtractLayer::new()
+            .with_trace_id_header(HeaderName::from_static(TRACE_ID_HEADER))
+            .with_traceparent_header();
+
+        // trailers-only error response
+        let response = ServiceBuilder::new()
+            .layer(layer.clone())
+            .service(service_fn(|_request| respond(tonic::Status::internal("boom").to_http())))
+            .oneshot(grpc_request(MetadataMap::new()))
+            .await
+            .unwrap();
+        let status = tonic::Status::from_header_map(response.headers()).unwrap();
+        drop(response);
+        let span = recorder.span("helloworld.Greeter/SayHello");
+        assert_eq!(trace_id_from_status(&status), Some(span.span_context.trace_id()));
+        let traceparent = status.metadata().get("traceparent").unwrap().to_str().unwrap();
+        assert!(traceparent.contains(&span.span_context.span_id().to_string()));
+
+        // error status in the trailers, after messages
+        let mut trailers = http::HeaderMap::new();
+        trailers.insert("grpc-status", "13".parse().unwrap());
+        let mut response = ServiceBuilder::new()
+            .layer(layer)
+            .service(service_fn(move |_request: http::Request<()>| {
+                let body = TrailersBody(Some(trailers.clone()));
+                async move { Ok::<_, Infallible>(http::Response::new(body)) }
+            }))
+            .oneshot(grpc_request(MetadataMap::new()))
+            .await
+            .unwrap();
+        let trace_id = response.headers()[TRACE_ID_HEADER].to_str().unwrap().to_string();
+        let trailers = response.body_mut().trailers().await.unwrap().unwrap();
+        assert_eq!(trailers[TRACE_ID_HEADER], trace_id.as_str());
+        assert_ne!(trace_id, span.span_context.trace_id().to_string());
+    }
+
+    #[tokio::test]
+    async fn cancelled() {
+        let recorder = SpanRecorder::default();
+        let (_provider, subscriber) = recorder.subscriber();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        // handler still running
+        let mut handler = ServiceBuilder::new()
+            .layer(TraceExtractLayer::new())
+            .service(service_fn(|_request: http::Request<()>| {
+                std::future::pending::<Result<http::Response<BoxBody>, Infallible>>()
+            }))
+            .oneshot(grpc_request(MetadataMap::new()));
+        std::future::poll_fn(|cx| {
+            assert!(Pin::new(&mut handler).poll(cx).is_pending());
+            Poll::Ready(())
+        })
+        .await;
+        drop(handler);
+
+        // response body dropped before the trailers
+        let mut trailers = http::HeaderMap::new();
+        trailers.insert("grpc-status", "0".parse().unwrap());
+        let response = ServiceBuilder::new()
+            .layer(TraceExtractLayer::new())
+            .service(service_fn(move |_request: http::Request<()>| {
+                let body = TrailersBody(Some(trailers.clone()));
+                async move { Ok::<_, Infallible>(http::Response::new(body)) }
+            }))
+            .oneshot(grpc_request(MetadataMap::new()))
+            .await
+            .unwrap();
+        drop(response);
+
+        let spans = recorder.spans();
+        assert_eq!(spans.len(), 2);
+        for span in spans {
+            assert_eq!(attribute(&span, "rpc.grpc.status_code"), Some(Value::I64(1)));
+            assert_eq!(span.status, Status::Unset);
+            assert!(span.events.iter().any(|event| event.name == "cancelled"));
+        }
+    }
+}
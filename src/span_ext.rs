@@ -0,0 +1,28 @@
+use opentelemetry::KeyValue;
+
+use tracing_opentelemetry::OtelData;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Registry;
+
+
+// tracing fields must be declared when the span is created, attributes only known at runtime
+// (e.g. baggage keys) are written into the otel span data kept by tracing-opentelemetry instead.
+// Does nothing unless the subscriber is built on tracing_subscriber::Registry.
+pub(crate) fn set_attributes<I>(span: &tracing::Span, attributes: I)
+where
+    I: IntoIterator<Item = KeyValue>,
+{
+    span.with_subscriber(|(id, dispatch)| {
+        let span = match dispatch.downcast_ref::<Registry>().and_then(|registry| registry.span(id)) {
+            Some(span) => span,
+            None => return,
+        };
+        let mut extensions = span.extensions_mut();
+        if let Some(data) = extensions.get_mut::<OtelData>() {
+            let builder_attributes = data.builder.attributes.get_or_insert_with(Default::default);
+            for KeyValue { key, value } in attributes {
+                builder_attributes.insert(key, value);
+            }
+        }
+    });
+}
@@ -0,0 +1,184 @@
+use std::pin::Pin;
+use std::task::{Context as TaskContext, Poll};
+
+use futures_core::Stream;
+use pin_project::pin_project;
+use prost::Message;
+use tonic::Status;
+
+
+/// Message stream of a streaming RPC, adding a `message` event to a span for every message,
+/// with `message.type` (SENT/RECEIVED), `message.id` (from 1) and `message.uncompressed_size`.
+///
+/// Events go to the span current when the stream is wrapped, i.e. the server span inside a handler
+/// behind [`TraceExtractLayer`](crate::TraceExtractLayer). Use [`in_span`](Self::in_span) otherwise.
+///
+/// ```ignore
+/// // server handler
+/// let mut requests = TracedStream::received(request.into_inner());
+/// let responses = TracedStream::sent_responses(ReceiverStream::new(rx));
+/// Ok(Response::new(Box::pin(responses) as Self::ChatStream))
+///
+/// // client
+/// let responses = client.chat(TracedStream::sent(outbound)).await?;
+/// let mut responses = TracedStream::received(responses.into_inner());
+/// ```
+#[pin_project]
+pub struct TracedStream<S: Stream> {
+    #[pin]
+    inner: S,
+    span: tracing::Span,
+    message_type: MessageType,
+    message_id: i64,
+    size: fn(&S::Item) -> Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum MessageType {
+    Sent,
+    Received,
+}
+
+impl<S, T> TracedStream<S>
+where
+    S: Stream<Item = T>,
+    T: Message,
+{
+    /// Outbound request messages of a client- or bidi-streaming call.
+    pub fn sent(stream: S) -> Self {
+        Self::new(stream, MessageType::Sent, |message| Some(message.encoded_len()))
+    }
+}
+
+impl<S, T> TracedStream<S>
+where
+    S: Stream<Item = Result<T, Status>>,
+    T: Message,
+{
+    /// Inbound messages, e.g. a `tonic::Streaming<T>` on either side.
+    /// The final `Err(Status)`, if any, is not a message.
+    pub fn received(stream: S) -> Self {
+        Self::new(stream, MessageType::Received, result_size)
+    }
+
+    /// Outbound response messages of a server- or bidi-streaming handler.
+    pub fn sent_responses(stream: S) -> Self {
+        Self::new(stream, MessageType::Sent, result_size)
+    }
+}
+
+impl<S: Stream> TracedStream<S> {
+    fn new(inner: S, message_type: MessageType, size: fn(&S::Item) -> Option<usize>) -> Self {
+        TracedStream {
+            inner,
+            span: tracing::Span::current(),
+            message_type,
+            message_id: 0,
+            size,
+        }
+    }
+
+    /// Add the message events to `span` instead of the current span.
+    pub fn in_span(mut self, span: tracing::Span) -> Self {
+        self.span = span;
+        self
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: Stream> Stream for TracedStream<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let item = match this.inner.poll_next(cx) {
+            Poll::Ready(Some(item)) => item,
+            other => return other,
+        };
+
+        if let Some(size) = (this.size)(&item) {
+            *this.message_id += 1;
+            let message_id = *this.message_id;
+            let message_type = match this.message_type {
+                MessageType::Sent => "SENT",
+                MessageType::Received => "RECEIVED",
+            };
+            // tracing-opentelemetry attaches events to the entered span, not the explicit parent
+            this.span.in_scope(|| {
+                tracing::info!(
+                    message.r#type = message_type,
+                    message.id = message_id,
+                    message.uncompressed_size = size as i64,
+                    "message",
+                )
+            });
+        }
+        Poll::Ready(Some(item))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+fn result_size<T: Message>(result: &Result<T, Status>) -> Option<usize> {
+    result.as_ref().ok().map(Message::encoded_len)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::Value;
+    use tokio_stream::StreamExt;
+    use tonic::Status;
+
+    use super::TracedStream;
+    use crate::testing::SpanRecorder;
+
+    #[tokio::test]
+    async fn message_events() {
+        let recorder = SpanRecorder::default();
+        let (_provider, subscriber) = recorder.subscriber();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let span = tracing::info_span!("chat");
+        let sent: Vec<_> = span
+            .in_scope(|| TracedStream::sent(tokio_stream::iter(vec!["hello".to_string(), String::new()])))
+            .collect()
+            .await;
+        assert_eq!(sent.len(), 2);
+
+        let received = tokio_stream::iter(vec![Ok("hi".to_string()), Err(Status::cancelled("bye"))]);
+        let received: Vec<_> = TracedStream::received(received).in_span(span.clone()).collect().await;
+        assert_eq!(received.len(), 2);
+        drop(span);
+
+        let events: Vec<_> = recorder
+            .span("chat")
+            .events
+            .iter()
+            .map(|event| {
+                let attribute = |key: &str| {
+                    event.attributes.iter().find(|kv| kv.key.as_str() == key).map(|kv| kv.value.clone())
+                };
+                (
+                    event.name.to_string(),
+                    attribute("message.type"),
+                    attribute("message.id"),
+                    attribute("message.uncompressed_size"),
+                )
+            })
+            .collect();
+        assert_eq!(
+            events,
+            [
+                ("message".to_string(), Some(Value::from("SENT")), Some(Value::I64(1)), Some(Value::I64(7))),
+                ("message".to_string(), Some(Value::from("SENT")), Some(Value::I64(2)), Some(Value::I64(0))),
+                ("message".to_string(), Some(Value::from("RECEIVED")), Some(Value::I64(1)), Some(Value::I64(4))),
+            ]
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use std::convert::Infallible;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context as TaskContext, Poll};
+
+use http_body::Body;
+
+use opentelemetry::baggage::BaggageExt;
+use opentelemetry::propagation::{text_map_propagator::FieldIter, Extractor, Injector, TextMapPropagator};
+use opentelemetry::sdk::export::trace::SpanData;
+use opentelemetry::sdk::trace::{Span, SpanProcessor, TracerProvider};
+use opentelemetry::trace::{TraceResult, TracerProvider as _};
+use opentelemetry::{Context, KeyValue, Value};
+use tonic::codegen::Bytes;
+use tracing_subscriber::layer::SubscriberExt;
+
+
+// processor keeping finished spans in memory, synchronously unlike the simple exporter
+#[derive(Debug, Clone, Default)]
+pub struct SpanRecorder(Arc<Mutex<Vec<SpanData>>>);
+
+impl SpanProcessor for SpanRecorder {
+    fn on_start(&self, _span: &mut Span, _cx: &Context) {}
+
+    fn on_end(&self, span: SpanData) {
+        self.0.lock().unwrap().push(span);
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        Ok(())
+    }
+}
+
+impl SpanRecorder {
+    pub fn spans(&self) -> Vec<SpanData> {
+        self.0.lock().unwrap().clone()
+    }
+
+    pub fn span(&self, name: &str) -> SpanData {
+        self.spans()
+            .into_iter()
+            .find(|span| span.name == name)
+            .unwrap_or_else(|| panic!("no span named {}", name))
+    }
+
+    // provider has to outlive the subscriber, spans are dropped once it is gone
+    pub fn subscriber(&self) -> (TracerProvider, impl tracing::Subscriber + Send + Sync) {
+        let provider = TracerProvider::builder()
+            .with_span_processor(self.clone())
+            .build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        (provider, subscriber)
+    }
+}
+
+// sends the "tenant" baggage as is in "x-tenant", which may not be valid metadata
+#[derive(Debug)]
+pub struct TenantPropagator(Vec<String>);
+
+impl TenantPropagator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Default for TenantPropagator {
+    fn default() -> Self {
+        TenantPropagator(vec!["x-tenant".to_string()])
+    }
+}
+
+impl TextMapPropagator for TenantPropagator {
+    fn inject_context(&self, cx: &Context, injector: &mut dyn Injector) {
+        if let Some(tenant) = cx.baggage().get("tenant") {
+            injector.set("x-tenant", tenant.to_string());
+        }
+    }
+
+    fn extract_with_context(&self, cx: &Context, extractor: &dyn Extractor) -> Context {
+        match extractor.get("x-tenant") {
+            Some(tenant) => cx.with_baggage(vec![KeyValue::new("tenant", tenant.to_string())]),
+            None => cx.clone(),
+        }
+    }
+
+    fn fields(&self) -> FieldIter<'_> {
+        FieldIter::new(&self.0)
+    }
+}
+
+pub fn attribute(span: &SpanData, key: &'static str) -> Option<Value> {
+    span.attributes.get(&key.into()).cloned()
+}
+
+// empty body ending with the given trailers
+pub struct TrailersBody(pub Option<http::HeaderMap>);
+
+impl Body for TrailersBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_data(
+        self: Pin<&mut Self>,
+        _cx: &mut TaskContext<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        Poll::Ready(None)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        _cx: &mut TaskContext<'_>,
+    ) -> Poll<Result<Option<http::HeaderMap>, Self::Error>> {
+        Poll::Ready(Ok(self.0.take()))
+    }
+}
@@ -0,0 +1,64 @@
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry::trace::{TraceContextExt, TraceId};
+use opentelemetry::Context;
+
+use tonic::metadata::MetadataMap;
+use tonic::{Response, Status};
+
+use crate::MetadataExtractor;
+
+
+/// Usual response header for the server trace id, see `TraceExtractLayer::with_trace_id_header`.
+pub const TRACE_ID_HEADER: &str = "x-trace-id";
+
+
+/// Server trace id returned with a successful response, from [`TRACE_ID_HEADER`] or `traceparent`.
+pub fn trace_id_from_response<T>(response: &Response<T>) -> Option<TraceId> {
+    trace_id_from_metadata(response.metadata(), TRACE_ID_HEADER)
+}
+
+/// Server trace id returned with an error status, from [`TRACE_ID_HEADER`] or `traceparent`.
+///
+/// e.g. `Err(status) => error!(server_trace_id = ?trace_id_from_status(&status), "call failed")`
+pub fn trace_id_from_status(status: &Status) -> Option<TraceId> {
+    trace_id_from_metadata(status.metadata(), TRACE_ID_HEADER)
+}
+
+/// Server trace id from the `header` entry, falling back to `traceparent`.
+pub fn trace_id_from_metadata(metadata: &MetadataMap, header: &str) -> Option<TraceId> {
+    let trace_id = metadata
+        .get(header)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| TraceId::from_hex(value).ok())
+        .unwrap_or_else(|| {
+            let cx = TraceContextPropagator::new().extract_with_context(&Context::new(), &MetadataExtractor(metadata));
+            cx.span().span_context().trace_id()
+        });
+    (trace_id != TraceId::INVALID).then_some(trace_id)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use tonic::metadata::MetadataMap;
+    use tonic::{Code, Status};
+
+    use super::{trace_id_from_metadata, trace_id_from_status};
+
+    #[test]
+    fn read_trace_id() {
+        let mut metadata = MetadataMap::new();
+        assert_eq!(trace_id_from_metadata(&metadata, "x-trace-id"), None);
+
+        metadata.insert("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".parse().unwrap());
+        let status = Status::with_metadata(Code::Internal, "boom", metadata.clone());
+        assert_eq!(trace_id_from_status(&status).unwrap().to_string(), "4bf92f3577b34da6a3ce929d0e0e4736");
+
+        metadata.insert("x-request-trace", "0af7651916cd43dd8448eb211c80319c".parse().unwrap());
+        assert_eq!(
+            trace_id_from_metadata(&metadata, "x-request-trace").unwrap().to_string(),
+            "0af7651916cd43dd8448eb211c80319c"
+        );
+    }
+}
@@ -0,0 +1,224 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context as TaskContext, Poll};
+
+use opentelemetry::global;
+use opentelemetry::propagation::TextMapPropagator;
+use pin_project::{pin_project, pinned_drop};
+use tonic::{Request, Status};
+
+// extend tracing::Span with set_parent() & context()
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::semconv::{
+    new_rpc_span, parse_grpc_timeout, record_cancelled, record_status, RpcKind, NETWORK_PEER_ADDRESS,
+    RPC_GRPC_TIMEOUT_MS,
+};
+use crate::{CheckedMetadataInjector, MetadataExtractor};
+
+
+// runtime of the `#[traced_rpc]` attribute and of the wrappers written by `build::generate`
+
+// server span of a tonic service method
+pub fn server_span<T>(request: &Request<T>, service: &str, method: &str) -> tracing::Span {
+    global::get_text_map_propagator(|propagator| server_span_with(request, propagator, service, method))
+}
+
+// same as server_span, with an explicit propagator instead of the global one
+pub fn server_span_with<T>(
+    request: &Request<T>,
+    propagator: &dyn TextMapPropagator,
+    service: &str,
+    method: &str,
+) -> tracing::Span {
+    let span = new_rpc_span(RpcKind::Server, &format!("{}/{}", service, method), Some(service), Some(method));
+    span.set_parent(propagator.extract(&MetadataExtractor(request.metadata())));
+
+    let timeout = request
+        .metadata()
+        .get("grpc-timeout")
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_grpc_timeout);
+    if let Some(timeout) = timeout {
+        span.record(RPC_GRPC_TIMEOUT_MS, timeout.as_millis() as i64);
+    }
+    if let Some(peer) = request.remote_addr() {
+        span.record(NETWORK_PEER_ADDRESS, tracing::field::display(peer.ip()));
+    }
+    span
+}
+
+// client span of a call, child of the current tracing span
+pub fn client_span(service: &str, method: &str) -> tracing::Span {
+    new_rpc_span(RpcKind::Client, &format!("{}/{}", service, method), Some(service), Some(method))
+}
+
+// send the context of the client span with the request
+pub fn inject<T>(span: &tracing::Span, request: &mut Request<T>) {
+    global::get_text_map_propagator(|propagator| inject_with(span, request, propagator))
+}
+
+// same as inject, with an explicit propagator instead of the global one
+pub fn inject_with<T>(span: &tracing::Span, request: &mut Request<T>, propagator: &dyn TextMapPropagator) {
+    let cx = span.context();
+    let mut injector = CheckedMetadataInjector::new(request.metadata_mut());
+    propagator.inject_context(&cx, &mut injector);
+    // rejected entries are logged with tracing::warn!
+    let _report = injector.into_report();
+}
+
+pub fn traced<F>(span: tracing::Span, inner: F) -> TracedRpc<F> {
+    TracedRpc {
+        inner,
+        span,
+        kind: RpcKind::Server,
+        completed: false,
+    }
+}
+
+pub fn traced_client<F>(span: tracing::Span, inner: F) -> TracedRpc<F> {
+    TracedRpc {
+        inner,
+        span,
+        kind: RpcKind::Client,
+        completed: false,
+    }
+}
+
+// method or call future running in the span, records the returned status,
+// or CANCELLED when a server drops it before
+#[pin_project(PinnedDrop)]
+pub struct TracedRpc<F> {
+    #[pin]
+    inner: F,
+    span: tracing::Span,
+    kind: RpcKind,
+    completed: bool,
+}
+
+impl<F, T> Future for TracedRpc<F>
+where
+    F: Future<Output = Result<T, Status>>,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let _enter = this.span.enter();
+
+        let result = match this.inner.poll(cx) {
+            Poll::Ready(result) => result,
+            Poll::Pending => return Poll::Pending,
+        };
+        *this.completed = true;
+        match &result {
+            Ok(_) => record_status(this.span, *this.kind, &Status::ok("")),
+            Err(status) => record_status(this.span, *this.kind, status),
+        }
+        Poll::Ready(result)
+    }
+}
+
+#[pinned_drop]
+impl<F> PinnedDrop for TracedRpc<F> {
+    fn drop(self: Pin<&mut Self>) {
+        let this = self.project();
+        if !*this.completed && *this.kind == RpcKind::Server {
+            record_cancelled(this.span);
+        }
+    }
+}
+
+
+#[cfg(all(test, feature = "macros"))]
+mod tests {
+    use opentelemetry::sdk::propagation::TraceContextPropagator;
+    use opentelemetry::trace::{SpanKind, Status as SpanStatus};
+    use opentelemetry::{global, Value};
+    use tonic::{Request, Response, Status};
+
+    use crate::testing::{attribute, SpanRecorder};
+    use crate::traced_rpc;
+    use super::{client_span, inject_with, server_span_with};
+
+    struct Greeter;
+
+    #[tonic::async_trait]
+    trait Greet {
+        async fn say_hello(&self, request: Request<String>) -> Result<Response<String>, Status>;
+    }
+
+    #[tonic::async_trait]
+    impl Greet for Greeter {
+        #[traced_rpc(service = "helloworld.Greeter")]
+        async fn say_hello(&self, request: Request<String>) -> Result<Response<String>, Status> {
+            tracing::info!("handling");
+            let name = request.into_inner();
+            if name.is_empty() {
+                return Err(Status::invalid_argument("name is empty"));
+            }
+            Ok(Response::new(format!("hello {}", name)))
+        }
+    }
+
+    impl Greeter {
+        #[traced_rpc(service = "helloworld.Greeter", method = "Fail")]
+        async fn fail(&self, _request: Request<()>) -> Result<Response<()>, Status> {
+            Err(Status::internal("boom"))
+        }
+    }
+
+    #[tokio::test]
+    async fn traced_methods() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let recorder = SpanRecorder::default();
+        let (_provider, subscriber) = recorder.subscriber();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let mut request = Request::new("world".to_string());
+        request
+            .metadata_mut()
+            .insert("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".parse().unwrap());
+        let reply = Greeter.say_hello(request).await.unwrap();
+        assert_eq!(reply.into_inner(), "hello world");
+        assert!(Greeter.say_hello(Request::new(String::new())).await.is_err());
+        assert!(Greeter.fail(Request::new(())).await.is_err());
+
+        let spans = recorder.spans();
+        let names: Vec<_> = spans.iter().map(|span| span.name.to_string()).collect();
+        assert_eq!(names, ["helloworld.Greeter/SayHello", "helloworld.Greeter/SayHello", "helloworld.Greeter/Fail"]);
+
+        let ok = &spans[0];
+        assert_eq!(ok.span_kind, SpanKind::Server);
+        assert_eq!(ok.span_context.trace_id().to_string(), "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(attribute(ok, "rpc.method"), Some(Value::from("SayHello")));
+        assert_eq!(attribute(ok, "rpc.grpc.status_code"), Some(Value::I64(0)));
+        assert!(ok.events.iter().any(|event| event.name == "handling"));
+
+        // InvalidArgument is the client's fault
+        assert_eq!(attribute(&spans[1], "rpc.grpc.status_code"), Some(Value::I64(3)));
+        assert_eq!(spans[1].status, SpanStatus::Unset);
+        assert_eq!(spans[2].status, SpanStatus::error("boom"));
+    }
+
+    #[test]
+    fn explicit_propagator() {
+        let propagator = TraceContextPropagator::new();
+        let recorder = SpanRecorder::default();
+        let (_provider, subscriber) = recorder.subscriber();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let mut request = Request::new(());
+        let client = client_span("helloworld.Greeter", "SayHello");
+        inject_with(&client, &mut request, &propagator);
+        assert!(request.metadata().get("traceparent").is_some());
+
+        let server = server_span_with(&request, &propagator, "helloworld.Greeter", "SayHello");
+        drop(server);
+        drop(client);
+
+        let spans = recorder.spans();
+        assert_eq!(spans[0].span_kind, SpanKind::Server);
+        assert_eq!(spans[0].parent_span_id, spans[1].span_context.span_id());
+    }
+}
@@ -0,0 +1,156 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use opentelemetry::trace::TraceContextExt;
+use opentelemetry::Context;
+
+use http::request::Parts;
+use http::Extensions;
+use tonic::transport::server::TcpConnectInfo;
+#[cfg(feature = "tls")]
+use tonic::transport::server::TlsConnectInfo;
+
+// extend tracing::Span with set_parent() & add_link()
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+
+/// What a server does with the trace context sent by the client, e.g. to stop external callers
+/// from forcing sampling or joining their traces to ours.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TrustPolicy {
+    /// Parent the server span on the remote context.
+    #[default]
+    Trust,
+    /// Start a new trace, the remote context is dropped.
+    Ignore,
+    /// Start a new trace with a span link to the remote span context.
+    LinkOnly,
+}
+
+impl TrustPolicy {
+    /// Apply the policy to `span` for the context `cx` extracted from the request.
+    pub fn apply(self, span: &tracing::Span, cx: Context) {
+        match self {
+            TrustPolicy::Trust => span.set_parent(cx),
+            TrustPolicy::Ignore => span.set_parent(Context::new()),
+            TrustPolicy::LinkOnly => {
+                span.set_parent(Context::new());
+                let remote = cx.span().span_context().clone();
+                if remote.is_valid() {
+                    span.add_link(remote);
+                }
+            }
+        }
+    }
+}
+
+
+// policy of the server layer, fixed or chosen per request
+#[derive(Clone)]
+pub(crate) enum TrustSelector {
+    Fixed(TrustPolicy),
+    Select(Arc<dyn Fn(&Parts) -> TrustPolicy + Send + Sync>),
+}
+
+impl TrustSelector {
+    pub(crate) fn select(&self, parts: &Parts) -> TrustPolicy {
+        match self {
+            TrustSelector::Fixed(policy) => *policy,
+            TrustSelector::Select(select) => select(parts),
+        }
+    }
+}
+
+impl Default for TrustSelector {
+    fn default() -> Self {
+        TrustSelector::Fixed(TrustPolicy::Trust)
+    }
+}
+
+impl fmt::Debug for TrustSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrustSelector::Fixed(policy) => write!(f, "Fixed({:?})", policy),
+            TrustSelector::Select(_) => f.write_str("Select"),
+        }
+    }
+}
+
+
+/// Remote address of the request, set by the tonic transport server, like `tonic::Request::remote_addr`.
+/// On a TLS server the address is only found with the `tls` feature.
+pub fn peer_addr(parts: &Parts) -> Option<SocketAddr> {
+    remote_addr(&parts.extensions)
+}
+
+// TcpConnectInfo of a plain connection, wrapped in TlsConnectInfo behind TLS
+pub(crate) fn remote_addr(extensions: &Extensions) -> Option<SocketAddr> {
+    let addr = extensions.get::<TcpConnectInfo>().and_then(TcpConnectInfo::remote_addr);
+    #[cfg(feature = "tls")]
+    let addr = addr.or_else(|| extensions.get::<TlsConnectInfo<TcpConnectInfo>>()?.get_ref().remote_addr());
+    addr
+}
+
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState};
+    use opentelemetry::Context;
+
+    use tonic::transport::server::Connected;
+
+    use super::{peer_addr, TrustPolicy};
+    use crate::testing::SpanRecorder;
+
+    #[test]
+    fn policies() {
+        let recorder = SpanRecorder::default();
+        let (_provider, subscriber) = recorder.subscriber();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let remote = SpanContext::new(
+            TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap(),
+            SpanId::from_hex("00f067aa0ba902b7").unwrap(),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        );
+        for (name, policy) in [
+            ("trust", TrustPolicy::Trust),
+            ("ignore", TrustPolicy::Ignore),
+            ("link", TrustPolicy::LinkOnly),
+        ] {
+            let span = tracing::info_span!("server", otel.name = name);
+            policy.apply(&span, Context::new().with_remote_span_context(remote.clone()));
+        }
+
+        let trust = recorder.span("trust");
+        assert_eq!(trust.span_context.trace_id(), remote.trace_id());
+        assert_eq!(trust.parent_span_id, remote.span_id());
+        assert!(trust.links.is_empty());
+
+        let ignore = recorder.span("ignore");
+        assert_ne!(ignore.span_context.trace_id(), remote.trace_id());
+        assert_eq!(ignore.parent_span_id, SpanId::INVALID);
+        assert!(ignore.links.is_empty());
+
+        let link = recorder.span("link");
+        assert_ne!(link.span_context.trace_id(), remote.trace_id());
+        assert_eq!(link.parent_span_id, SpanId::INVALID);
+        let links: Vec<_> = link.links.iter().map(|link| link.span_context.clone()).collect();
+        assert_eq!(links, [remote]);
+    }
+
+    #[tokio::test]
+    async fn tcp_peer_addr() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = tokio::net::TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+
+        let (mut parts, ()) = http::Request::new(()).into_parts();
+        assert_eq!(peer_addr(&parts), None);
+        parts.extensions.insert(server.connect_info());
+        assert_eq!(peer_addr(&parts), Some(client.local_addr().unwrap()));
+    }
+}